@@ -0,0 +1,179 @@
+use crate::html_writer::OutputMode;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The name of the file GoodWeb reads its configuration from, relative to the website.
+pub const CONFIG_FILE: &str = "goodweb.json";
+
+/// The project configuration, read from `goodweb.json`. Every field is optional, so a website
+/// without a configuration file builds just fine.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Component packages, keyed by the namespace their components are placed in.
+    pub packages: BTreeMap<String, PackageSource>,
+    /// Theme directories, each with a `components` directory. Components the website doesn't
+    /// have are looked up in the themes, in order.
+    pub themes: Vec<String>,
+    /// How pages are written, either `"html5"` (the default) or `"xml"`.
+    pub output: OutputMode,
+    /// Which comments make it into the output.
+    pub comments: CommentConfig,
+    /// What's done to the CSS of every page.
+    pub css: CssConfig,
+    /// Design tokens like colours and spacing, which templates can use as `{{ tokens.name }}`
+    /// and CSS as `var(--name)`. Tokens can be grouped, `{ "color": { "primary": "#07f" } }` is
+    /// `tokens.color.primary` and `--color-primary`.
+    pub tokens: Map<String, Value>,
+}
+
+impl Config {
+    /// Every token as a CSS custom property, e.g. `--color-primary` with the value `#07f`.
+    pub fn token_properties(&self) -> Vec<(String, String)> {
+        let mut properties = Vec::new();
+        collect_token_properties(&mut properties, "-", &self.tokens);
+        properties
+    }
+}
+
+fn collect_token_properties(
+    properties: &mut Vec<(String, String)>,
+    prefix: &str,
+    tokens: &Map<String, Value>,
+) {
+    for (name, value) in tokens {
+        let property = format!("{}-{}", prefix, name);
+
+        match value {
+            Value::String(value) => properties.push((property, value.clone())),
+            Value::Number(value) => properties.push((property, value.to_string())),
+            Value::Object(tokens) => collect_token_properties(properties, &property, tokens),
+            _ => println!(
+                "[WARN] token '{}' should be a string, a number or a group of tokens",
+                &property[2..]
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageSource {
+    /// A directory, `.tar`, `.tar.gz` or `.tgz` file containing the package.
+    pub path: String,
+    /// If specified, the version the package must have.
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommentConfig {
+    /// Either `"marked"` (the default) to only keep comments starting with a `!`, like
+    /// `<!--! licence -->`, `"all"` or `"none"`.
+    pub keep: KeepComments,
+    /// Whether comments are computed by the template engine, like text.
+    pub templated: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CssConfig {
+    /// Whether rules that don't match anything on the page are removed. Off by default.
+    pub prune: bool,
+    /// Classes, ids and attributes that are added after the page is built, e.g. `.is-open`,
+    /// `#modal` or `[data-state]`, so rules using them are kept. `.js-*` keeps every class
+    /// starting with `js-`.
+    pub safelist: Vec<String>,
+    /// Which names are shortened, in both the HTML and the CSS.
+    pub rename: RenameConfig,
+    /// If set, CSS used by many pages is moved into a stylesheet they all share.
+    pub shared: Option<SharedConfig>,
+    /// Whether stylesheets are named after a hash of their contents, so they can be cached
+    /// forever.
+    pub hash: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SharedConfig {
+    /// How many pages have to use a component for its CSS to be shared. 2 by default.
+    pub pages: usize,
+}
+
+impl std::default::Default for SharedConfig {
+    #[inline]
+    fn default() -> SharedConfig {
+        SharedConfig { pages: 2 }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenameConfig {
+    /// Whether classes are renamed to names as short as possible.
+    pub classes: bool,
+    /// Whether ids are renamed too, along with everything that refers to them like `for`,
+    /// `href="#..."` and `url(#...)`.
+    pub ids: bool,
+    /// Classes and ids that keep their name, written like the safelist. Everything on the
+    /// safelist keeps its name too.
+    pub exclude: Vec<String>,
+}
+
+impl std::default::Default for CssConfig {
+    #[inline]
+    fn default() -> CssConfig {
+        CssConfig {
+            prune: false,
+            safelist: Vec::new(),
+            rename: RenameConfig::default(),
+            shared: None,
+            hash: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeepComments {
+    None,
+    Marked,
+    All,
+}
+
+impl std::default::Default for KeepComments {
+    #[inline]
+    fn default() -> KeepComments {
+        KeepComments::Marked
+    }
+}
+
+pub fn load_config() -> Option<Config> {
+    let path = Path::new(CONFIG_FILE);
+
+    if !path.exists() {
+        return Some(Config::default());
+    }
+
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(_) => {
+            println!("[ERR] couldn't read config '{}'", path.display());
+            return None;
+        }
+    };
+
+    match serde_json::from_str(&data) {
+        Ok(config) => Some(config),
+        Err(error) => {
+            println!(
+                "[ERR] couldn't parse config '{}': {}",
+                path.display(),
+                error
+            );
+            None
+        }
+    }
+}
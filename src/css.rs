@@ -0,0 +1,969 @@
+//! A small CSS parser, which understands just enough of CSS for GoodWeb to rewrite stylesheets.
+//!
+//! Stylesheets are parsed into a tree of rules, at-rules and declarations. Selectors are parsed
+//! so that they can be rewritten, values are kept exactly as they're written.
+
+use std::borrow::Cow;
+use std::fmt::{self, Display, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stylesheet {
+    pub nodes: Vec<CssNode>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CssNode {
+    /// `/*! licence */` - comments starting with a `!` are kept, the rest are removed.
+    Comment(String),
+    /// `color: red`
+    Declaration { property: String, value: String },
+    /// `.card, .panel { ... }`
+    Rule {
+        selectors: Vec<Selector>,
+        children: Vec<CssNode>,
+    },
+    /// `from { ... }` or `50% { ... }` inside of `@keyframes`
+    Keyframe {
+        selector: String,
+        children: Vec<CssNode>,
+    },
+    /// `@media screen { ... }`, or `@import "reset.css";` which has no block
+    AtRule {
+        name: String,
+        prelude: String,
+        children: Option<Vec<CssNode>>,
+    },
+}
+
+/// A complex selector, e.g. `.card > .title:hover`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selector {
+    pub compounds: Vec<Compound>,
+}
+
+/// A compound selector, e.g. `a.button:hover`, along with how it's related to the compound before.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Compound {
+    /// Only the first compound may not have a combinator, unless it's relative like `> .title`.
+    pub combinator: Option<Combinator>,
+    pub parts: Vec<SimpleSelector>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Combinator {
+    /// `a b`
+    Descendant,
+    /// `a > b`
+    Child,
+    /// `a + b`
+    NextSibling,
+    /// `a ~ b`
+    SubsequentSibling,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SimpleSelector {
+    /// `*`
+    Universal,
+    /// `&`, the parent of a nested rule
+    Nesting,
+    Type(String),
+    Class(String),
+    Id(String),
+    /// The inside of `[...]`, e.g. `type="text"`.
+    Attribute(String),
+    /// `:hover` or `:not(.a)`, with what's inside of the brackets.
+    PseudoClass(String, Option<String>),
+    /// `::before`, with what's inside of the brackets.
+    PseudoElement(String, Option<String>),
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    /// The file the error is in, once it's known.
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    /// Records which file the error is in, unless it's already known, e.g. because the error is
+    /// in a file that was imported.
+    pub fn in_file(mut self, path: &Path) -> ParseError {
+        if self.file.is_none() {
+            self.file = Some(path.to_owned());
+        }
+
+        self
+    }
+}
+
+/// Errors are written like `components/Card.css:3: expected '}'`.
+impl Display for ParseError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(
+                formatter,
+                "{}:{}: {}",
+                file.display(),
+                self.line,
+                self.message
+            ),
+            None => write!(formatter, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+impl Stylesheet {
+    pub fn parse(input: &str) -> Result<Stylesheet, ParseError> {
+        let mut parser = Parser { input, position: 0 };
+        let nodes = parser.parse_nodes(false, false)?;
+        Ok(Stylesheet { nodes })
+    }
+
+    /// Writes the stylesheet out, either readable or as small as possible.
+    pub fn write(&self, minify: bool) -> String {
+        let mut output = String::new();
+        write_nodes(&mut output, &self.nodes, 0, minify);
+        output
+    }
+
+    /// Scopes every selector to elements with the given attribute, so `.title` turns into
+    /// `.title[data-gw-1a2b3c4d]`. Anything inside of `:global(...)` is left alone.
+    pub fn scope(&mut self, attribute: &str) {
+        scope_nodes(&mut self.nodes, attribute);
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl Parser<'_> {
+    /// Parses everything up until the end of the current block, or the end of the input.
+    fn parse_nodes(
+        &mut self,
+        in_block: bool,
+        in_keyframes: bool,
+    ) -> Result<Vec<CssNode>, ParseError> {
+        let mut nodes = Vec::new();
+
+        loop {
+            self.skip_whitespace_and_comments(Some(&mut nodes))?;
+
+            match self.peek() {
+                None if in_block => return Err(self.error(self.position, "expected '}'")),
+                None => return Ok(nodes),
+                Some('}') if in_block => {
+                    self.position += 1;
+                    return Ok(nodes);
+                }
+                Some('}') => return Err(self.error(self.position, "unexpected '}'")),
+                Some(';') => {
+                    self.position += 1;
+                    continue;
+                }
+                Some(_) => (),
+            }
+
+            let start = self.position;
+            let prelude = self.read_prelude()?;
+
+            if self.peek() == Some('{') {
+                self.position += 1;
+
+                if let Some(at_rule) = prelude.strip_prefix('@') {
+                    let (name, prelude) = split_at_rule(at_rule);
+                    let children = self.parse_nodes(true, name.ends_with("keyframes"))?;
+
+                    nodes.push(CssNode::AtRule {
+                        name,
+                        prelude,
+                        children: Some(children),
+                    });
+                } else if in_keyframes {
+                    let children = self.parse_nodes(true, false)?;
+                    nodes.push(CssNode::Keyframe {
+                        selector: prelude,
+                        children,
+                    });
+                } else {
+                    let selectors = match parse_selectors(&prelude) {
+                        Some(selectors) => selectors,
+                        None => {
+                            return Err(
+                                self.error(start, &format!("invalid selector '{}'", prelude))
+                            )
+                        }
+                    };
+
+                    let children = self.parse_nodes(true, false)?;
+                    nodes.push(CssNode::Rule {
+                        selectors,
+                        children,
+                    });
+                }
+
+                continue;
+            }
+
+            // otherwise it's a statement, ended by a ';', the end of the block or the input
+            if let Some(at_rule) = prelude.strip_prefix('@') {
+                let (name, prelude) = split_at_rule(at_rule);
+                nodes.push(CssNode::AtRule {
+                    name,
+                    prelude,
+                    children: None,
+                });
+                continue;
+            }
+
+            match find_top_level(&prelude, ':') {
+                Some(colon) => nodes.push(CssNode::Declaration {
+                    property: prelude[..colon].trim().to_owned(),
+                    value: prelude[colon + 1..].trim().to_owned(),
+                }),
+                None => {
+                    return Err(self.error(
+                        start,
+                        &format!(
+                            "expected a declaration like 'property: value', found '{}'",
+                            prelude
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Reads everything up to the next `{`, `;` or `}` which isn't in brackets or a string.
+    /// Comments are removed.
+    fn read_prelude(&mut self) -> Result<String, ParseError> {
+        let start = self.position;
+        let mut prelude = String::new();
+        let mut depth = 0;
+
+        while let Some(character) = self.peek() {
+            match character {
+                '{' | ';' | '}' if depth == 0 => break,
+                '(' | '[' => depth += 1,
+                ')' | ']' if depth > 0 => depth -= 1,
+                '"' | '\'' => {
+                    let string = self.read_string()?;
+                    prelude.push_str(string);
+                    continue;
+                }
+                '\\' => {
+                    prelude.push(character);
+                    self.position += 1;
+
+                    if let Some(escaped) = self.peek() {
+                        prelude.push(escaped);
+                        self.position += escaped.len_utf8();
+                    }
+
+                    continue;
+                }
+                '/' if self.input[self.position..].starts_with("/*") => {
+                    self.skip_whitespace_and_comments(None)?;
+                    prelude.push(' ');
+                    continue;
+                }
+                _ => (),
+            }
+
+            prelude.push(character);
+            self.position += character.len_utf8();
+        }
+
+        if depth > 0 {
+            return Err(self.error(start, "unclosed bracket"));
+        }
+
+        Ok(prelude.trim().to_owned())
+    }
+
+    /// Reads a quoted string, quotes included.
+    fn read_string(&mut self) -> Result<&str, ParseError> {
+        let start = self.position;
+        let quote = self.peek().unwrap();
+        self.position += 1;
+
+        while let Some(character) = self.peek() {
+            self.position += character.len_utf8();
+
+            match character {
+                '\\' => {
+                    if let Some(escaped) = self.peek() {
+                        self.position += escaped.len_utf8();
+                    }
+                }
+                '\n' => break,
+                _ if character == quote => return Ok(&self.input[start..self.position]),
+                _ => (),
+            }
+        }
+
+        Err(self.error(start, "unclosed string"))
+    }
+
+    /// Skips whitespace and comments. Comments that are kept are added to the nodes, if given.
+    fn skip_whitespace_and_comments(
+        &mut self,
+        mut nodes: Option<&mut Vec<CssNode>>,
+    ) -> Result<(), ParseError> {
+        loop {
+            let rest = &self.input[self.position..];
+            let trimmed = rest.trim_start();
+            self.position += rest.len() - trimmed.len();
+
+            if !trimmed.starts_with("/*") {
+                return Ok(());
+            }
+
+            let end = match trimmed[2..].find("*/") {
+                Some(end) => end + 2,
+                None => return Err(self.error(self.position, "unclosed comment")),
+            };
+
+            if let Some(nodes) = nodes.as_mut() {
+                if trimmed[2..].starts_with('!') {
+                    nodes.push(CssNode::Comment(trimmed[2..end].to_owned()));
+                }
+            }
+
+            self.position += end + 2;
+        }
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+
+    fn error(&self, position: usize, message: &str) -> ParseError {
+        ParseError {
+            file: None,
+            line: self.input[..position].matches('\n').count() + 1,
+            message: message.to_owned(),
+        }
+    }
+}
+
+/// Splits `media screen` into `media` and `screen`.
+fn split_at_rule(at_rule: &str) -> (String, String) {
+    let end = at_rule
+        .find(|character: char| character.is_whitespace() || character == '(' || character == '"')
+        .unwrap_or(at_rule.len());
+
+    (
+        at_rule[..end].to_ascii_lowercase(),
+        at_rule[end..].trim().to_owned(),
+    )
+}
+
+/// Finds a character which isn't inside of brackets or a string.
+#[inline]
+fn find_top_level(text: &str, needle: char) -> Option<usize> {
+    top_level_indices(text, needle).into_iter().next()
+}
+
+/// Splits text by a character, ignoring any inside of brackets or strings.
+fn split_top_level(text: &str, separator: char) -> impl Iterator<Item = &str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+
+    for index in top_level_indices(text, separator) {
+        parts.push(&text[start..index]);
+        start = index + separator.len_utf8();
+    }
+
+    parts.push(&text[start..]);
+    parts.into_iter()
+}
+
+/// The indices of a character wherever it isn't inside of brackets or a string. Closing brackets
+/// are found too, as long as they aren't inside of a string.
+fn top_level_indices(text: &str, needle: char) -> Vec<usize> {
+    let mut indices = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (index, character) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        if quote.is_none() && character == needle && depth <= 0 {
+            indices.push(index);
+            continue;
+        }
+
+        match (quote, character) {
+            (_, '\\') => escaped = true,
+            (Some(open), _) if character == open => quote = None,
+            (Some(_), _) => (),
+            (None, '"') | (None, '\'') => quote = Some(character),
+            (None, '(') | (None, '[') => depth += 1,
+            (None, ')') | (None, ']') => depth -= 1,
+            _ => (),
+        }
+    }
+
+    indices
+}
+
+/// Parses a comma separated list of selectors.
+pub fn parse_selectors(text: &str) -> Option<Vec<Selector>> {
+    split_top_level(text, ',')
+        .map(|selector| parse_selector(selector.trim()))
+        .collect()
+}
+
+pub fn parse_selector(text: &str) -> Option<Selector> {
+    let mut cursor = Cursor { text, position: 0 };
+    let mut compounds = Vec::new();
+    let mut combinator = None;
+
+    loop {
+        let had_whitespace = cursor.skip_whitespace();
+
+        let next = match cursor.peek() {
+            Some(next) => next,
+            None => break,
+        };
+
+        let explicit = match next {
+            '>' => Some(Combinator::Child),
+            '+' => Some(Combinator::NextSibling),
+            '~' => Some(Combinator::SubsequentSibling),
+            _ => None,
+        };
+
+        if let Some(explicit) = explicit {
+            // two combinators in a row
+            if combinator.is_some() {
+                return None;
+            }
+
+            combinator = Some(explicit);
+            cursor.position += 1;
+            continue;
+        }
+
+        if had_whitespace && combinator.is_none() && !compounds.is_empty() {
+            combinator = Some(Combinator::Descendant);
+        }
+
+        let parts = parse_compound(&mut cursor)?;
+        compounds.push(Compound {
+            combinator: combinator.take(),
+            parts,
+        });
+    }
+
+    // a combinator with nothing after it
+    if compounds.is_empty() || combinator.is_some() {
+        return None;
+    }
+
+    Some(Selector { compounds })
+}
+
+fn parse_compound(cursor: &mut Cursor<'_>) -> Option<Vec<SimpleSelector>> {
+    let mut parts = Vec::new();
+
+    while let Some(next) = cursor.peek() {
+        let part = match next {
+            '*' => {
+                cursor.position += 1;
+                SimpleSelector::Universal
+            }
+            '&' => {
+                cursor.position += 1;
+                SimpleSelector::Nesting
+            }
+            '.' => {
+                cursor.position += 1;
+                SimpleSelector::Class(cursor.read_ident()?)
+            }
+            '#' => {
+                cursor.position += 1;
+                SimpleSelector::Id(cursor.read_ident()?)
+            }
+            '[' => SimpleSelector::Attribute(cursor.read_brackets('[', ']')?.trim().to_owned()),
+            ':' => {
+                cursor.position += 1;
+                let is_element = cursor.peek() == Some(':');
+                if is_element {
+                    cursor.position += 1;
+                }
+
+                let name = cursor.read_ident()?.to_ascii_lowercase();
+                let arguments = match cursor.peek() {
+                    Some('(') => Some(cursor.read_brackets('(', ')')?.trim().to_owned()),
+                    _ => None,
+                };
+
+                // these were pseudo-elements before `::` existed
+                let is_legacy_element = matches!(
+                    name.as_str(),
+                    "before" | "after" | "first-line" | "first-letter"
+                );
+
+                if is_element || is_legacy_element {
+                    SimpleSelector::PseudoElement(name, arguments)
+                } else {
+                    SimpleSelector::PseudoClass(name, arguments)
+                }
+            }
+            _ if is_ident_character(next) => SimpleSelector::Type(cursor.read_ident()?),
+            _ if next.is_whitespace() || matches!(next, '>' | '+' | '~') => break,
+            _ => return None,
+        };
+
+        parts.push(part);
+    }
+
+    if parts.is_empty() {
+        return None;
+    }
+
+    Some(parts)
+}
+
+struct Cursor<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl Cursor<'_> {
+    #[inline]
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    /// Returns whether there was any whitespace.
+    fn skip_whitespace(&mut self) -> bool {
+        let rest = &self.text[self.position..];
+        let trimmed = rest.trim_start();
+        self.position += rest.len() - trimmed.len();
+        rest.len() != trimmed.len()
+    }
+
+    fn read_ident(&mut self) -> Option<String> {
+        let mut ident = String::new();
+
+        while let Some(next) = self.peek() {
+            if next == '\\' {
+                ident.push(next);
+                self.position += 1;
+
+                let escaped = self.peek()?;
+                ident.push(escaped);
+                self.position += escaped.len_utf8();
+                continue;
+            }
+
+            if !is_ident_character(next) {
+                break;
+            }
+
+            ident.push(next);
+            self.position += next.len_utf8();
+        }
+
+        if ident.is_empty() {
+            None
+        } else {
+            Some(ident)
+        }
+    }
+
+    /// Reads a bracketed part of a selector, returning what's inside of the brackets.
+    fn read_brackets(&mut self, open: char, close: char) -> Option<&str> {
+        let start = self.position + open.len_utf8();
+        let end = start + find_top_level(&self.text[start..], close)?;
+
+        self.position = end + close.len_utf8();
+        Some(&self.text[start..end])
+    }
+}
+
+#[inline]
+fn is_ident_character(character: char) -> bool {
+    character.is_ascii_alphanumeric() || matches!(character, '-' | '_') || !character.is_ascii()
+}
+
+/// Resolves the escapes in an identifier, so `md\:flex` turns into the `md:flex` it matches in
+/// HTML.
+pub fn unescape_identifier(identifier: &str) -> Cow<'_, str> {
+    if !identifier.contains('\\') {
+        return Cow::Borrowed(identifier);
+    }
+
+    let mut output = String::with_capacity(identifier.len());
+    let mut characters = identifier.chars().peekable();
+
+    while let Some(character) = characters.next() {
+        if character != '\\' {
+            output.push(character);
+            continue;
+        }
+
+        // up to 6 hex digits, optionally followed by a single whitespace
+        let mut hex = String::new();
+        while let Some(digit) = characters.peek().filter(|digit| digit.is_ascii_hexdigit()) {
+            if hex.len() == 6 {
+                break;
+            }
+
+            hex.push(*digit);
+            characters.next();
+        }
+
+        if hex.is_empty() {
+            output.extend(characters.next());
+            continue;
+        }
+
+        characters.next_if(|next| next.is_whitespace());
+        let code = u32::from_str_radix(&hex, 16).unwrap_or(0xFFFD);
+        output.push(std::char::from_u32(code).unwrap_or('\u{FFFD}'));
+    }
+
+    Cow::Owned(output)
+}
+
+fn scope_nodes(nodes: &mut [CssNode], attribute: &str) {
+    for node in nodes.iter_mut() {
+        match node {
+            CssNode::Rule {
+                selectors,
+                children,
+            } => {
+                for selector in selectors.iter_mut() {
+                    scope_selector(selector, attribute);
+                }
+
+                scope_nodes(children, attribute);
+            }
+            // @media, @supports and the like. keyframes are never scoped
+            CssNode::AtRule {
+                children: Some(children),
+                ..
+            } => scope_nodes(children, attribute),
+            _ => (),
+        }
+    }
+}
+
+fn scope_selector(selector: &mut Selector, attribute: &str) {
+    let mut compounds = Vec::with_capacity(selector.compounds.len());
+
+    for mut compound in selector.compounds.drain(..) {
+        let global = compound.parts.iter().position(
+            |part| matches!(part, SimpleSelector::PseudoClass(name, Some(_)) if name == "global"),
+        );
+
+        let global = match global {
+            Some(global) => global,
+            None => {
+                // the scope has to come before any pseudo-elements
+                let index = compound
+                    .parts
+                    .iter()
+                    .position(|part| matches!(part, SimpleSelector::PseudoElement(..)))
+                    .unwrap_or(compound.parts.len());
+
+                compound
+                    .parts
+                    .insert(index, SimpleSelector::Attribute(attribute.to_owned()));
+                compounds.push(compound);
+                continue;
+            }
+        };
+
+        let inner = match &compound.parts[global] {
+            SimpleSelector::PseudoClass(_, Some(inner)) => parse_selector(inner),
+            _ => None,
+        };
+
+        match inner {
+            // `.a:global(.b)` turns into `.a.b`
+            Some(mut inner) if inner.compounds.len() == 1 => {
+                let parts = inner.compounds.remove(0).parts;
+                compound.parts.splice(global..global + 1, parts);
+                compounds.push(compound);
+            }
+            // `:global(.a .b)` turns into `.a .b`
+            Some(mut inner) if compound.parts.len() == 1 => {
+                inner.compounds[0].combinator = compound.combinator;
+                compounds.extend(inner.compounds);
+            }
+            // the browser will ignore the rule, just like it would've before
+            _ => compounds.push(compound),
+        }
+    }
+
+    selector.compounds = compounds;
+}
+
+fn write_nodes(output: &mut String, nodes: &[CssNode], depth: usize, minify: bool) {
+    for (index, node) in nodes.iter().enumerate() {
+        if !minify {
+            output.push_str(&"    ".repeat(depth));
+        }
+
+        match node {
+            CssNode::Comment(comment) => {
+                output.push_str("/*");
+                output.push_str(comment);
+                output.push_str("*/");
+            }
+            CssNode::Declaration { property, value } => {
+                output.push_str(property);
+                output.push_str(if minify { ":" } else { ": " });
+                output.push_str(value);
+
+                // the last declaration of a block doesn't need a semicolon
+                if !minify || index + 1 < nodes.len() {
+                    output.push(';');
+                }
+            }
+            CssNode::Rule {
+                selectors,
+                children,
+            } => {
+                write_selectors(output, selectors, minify);
+                write_block(output, children, depth, minify);
+            }
+            CssNode::Keyframe { selector, children } => {
+                output.push_str(selector);
+                write_block(output, children, depth, minify);
+            }
+            CssNode::AtRule {
+                name,
+                prelude,
+                children,
+            } => {
+                output.push('@');
+                output.push_str(name);
+
+                if !prelude.is_empty() {
+                    output.push(' ');
+                    output.push_str(prelude);
+                }
+
+                match children {
+                    Some(children) => write_block(output, children, depth, minify),
+                    None => output.push(';'),
+                }
+            }
+        }
+
+        if !minify {
+            output.push('\n');
+        }
+    }
+}
+
+fn write_block(output: &mut String, children: &[CssNode], depth: usize, minify: bool) {
+    if minify {
+        output.push('{');
+        write_nodes(output, children, depth + 1, minify);
+        output.push('}');
+        return;
+    }
+
+    output.push_str(" {\n");
+    write_nodes(output, children, depth + 1, minify);
+    output.push_str(&"    ".repeat(depth));
+    output.push('}');
+}
+
+pub fn write_selectors(output: &mut String, selectors: &[Selector], minify: bool) {
+    for (index, selector) in selectors.iter().enumerate() {
+        if index > 0 {
+            output.push_str(if minify { "," } else { ", " });
+        }
+
+        if minify {
+            write!(output, "{:#}", selector).unwrap();
+        } else {
+            write!(output, "{}", selector).unwrap();
+        }
+    }
+}
+
+/// Selectors are written without any unnecessary whitespace with `{:#}`.
+impl Display for Selector {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let compact = formatter.alternate();
+
+        for (index, compound) in self.compounds.iter().enumerate() {
+            let combinator = match (compound.combinator, compact) {
+                (Some(Combinator::Descendant), _) => " ",
+                (Some(Combinator::Child), false) => " > ",
+                (Some(Combinator::Child), true) => ">",
+                (Some(Combinator::NextSibling), false) => " + ",
+                (Some(Combinator::NextSibling), true) => "+",
+                (Some(Combinator::SubsequentSibling), false) => " ~ ",
+                (Some(Combinator::SubsequentSibling), true) => "~",
+                (None, _) => "",
+            };
+
+            // relative selectors like `> .title` don't start with a space
+            if index == 0 {
+                formatter.write_str(combinator.trim_start())?;
+            } else {
+                formatter.write_str(combinator)?;
+            }
+
+            for part in compound.parts.iter() {
+                if compact {
+                    write!(formatter, "{:#}", part)?;
+                } else {
+                    write!(formatter, "{}", part)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for SimpleSelector {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (prefix, name, arguments) = match self {
+            SimpleSelector::Universal => return formatter.write_str("*"),
+            SimpleSelector::Nesting => return formatter.write_str("&"),
+            SimpleSelector::Type(name) => return formatter.write_str(name),
+            SimpleSelector::Class(name) => return write!(formatter, ".{}", name),
+            SimpleSelector::Id(name) => return write!(formatter, "#{}", name),
+            SimpleSelector::Attribute(inner) => return write!(formatter, "[{}]", inner),
+            SimpleSelector::PseudoClass(name, arguments) => (":", name, arguments),
+            SimpleSelector::PseudoElement(name, arguments) => ("::", name, arguments),
+        };
+
+        formatter.write_str(prefix)?;
+        formatter.write_str(name)?;
+
+        let arguments = match arguments {
+            Some(arguments) => arguments,
+            None => return Ok(()),
+        };
+
+        // the arguments of `:not(.a, .b)` and the like are selectors too
+        let selectors = match name.as_str() {
+            "not" | "is" | "where" | "has" if formatter.alternate() => parse_selectors(arguments),
+            _ => None,
+        };
+
+        match selectors {
+            Some(selectors) => {
+                let mut compact = String::new();
+                write_selectors(&mut compact, &selectors, true);
+                write!(formatter, "({})", compact)
+            }
+            None => write!(formatter, "({})", arguments),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(css: &str, minify: bool) -> String {
+        Stylesheet::parse(css).unwrap().write(minify)
+    }
+
+    fn scoped(css: &str) -> String {
+        let mut stylesheet = Stylesheet::parse(css).unwrap();
+        stylesheet.scope("data-gw-1");
+        stylesheet.write(true)
+    }
+
+    #[test]
+    fn writes_what_it_parses() {
+        let css = "@import \"reset.css\";\n\
+                   .card > .title:hover, a[href^=\"http\"]::after {\n    color: red;\n}\n\
+                   @media (min-width: 600px) {\n    .card {\n        margin: 0 auto;\n    }\n}\n";
+
+        assert_eq!(round_trip(css, false), css);
+        assert_eq!(
+            round_trip(css, true),
+            "@import \"reset.css\";.card>.title:hover,a[href^=\"http\"]::after{color:red}\
+             @media (min-width: 600px){.card{margin:0 auto}}"
+        );
+    }
+
+    #[test]
+    fn keeps_important_comments() {
+        assert_eq!(
+            round_trip("/*! licence */ /* note */ .a { color: red; }", true),
+            "/*! licence */.a{color:red}"
+        );
+    }
+
+    #[test]
+    fn parses_keyframes() {
+        assert_eq!(
+            round_trip(
+                "@keyframes spin { from { opacity: 0 } 50% { opacity: 1 } }",
+                true
+            ),
+            "@keyframes spin{from{opacity:0}50%{opacity:1}}"
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        let error = Stylesheet::parse(".a {\n  color: red;\n\n.b { color: blue; }").unwrap_err();
+        assert!(error.line > 1);
+        assert!(error.file.is_none());
+
+        let error = error.in_file(Path::new("components/Card.css"));
+        assert!(error.to_string().starts_with("components/Card.css:"));
+    }
+
+    #[test]
+    fn scopes_every_compound() {
+        assert_eq!(
+            scoped(".card .title, p::before { color: red }"),
+            ".card[data-gw-1] .title[data-gw-1],p[data-gw-1]::before{color:red}"
+        );
+    }
+
+    #[test]
+    fn scopes_inside_of_media_but_not_keyframes() {
+        assert_eq!(
+            scoped("@media print { .a { color: red } } @keyframes x { to { color: red } }"),
+            "@media print{.a[data-gw-1]{color:red}}@keyframes x{to{color:red}}"
+        );
+    }
+
+    #[test]
+    fn leaves_global_selectors_alone() {
+        assert_eq!(
+            scoped(".card:global(.dark) { color: red }"),
+            ".card.dark{color:red}"
+        );
+        assert_eq!(
+            scoped(":global(body .theme) .card { color: red }"),
+            "body .theme .card[data-gw-1]{color:red}"
+        );
+    }
+
+    #[test]
+    fn unescapes_identifiers() {
+        assert_eq!(unescape_identifier("sm\\:flex"), "sm:flex");
+        assert_eq!(unescape_identifier("\\31 0"), "10");
+        assert_eq!(unescape_identifier("plain"), "plain");
+    }
+}
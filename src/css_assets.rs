@@ -0,0 +1,350 @@
+//! Copies the files CSS refers to with `url()` into the build, and points the CSS at the copies.
+//!
+//! A component's CSS is written into the stylesheet of every page that uses it, so a relative
+//! `url(hero.jpg)` would no longer be relative to the component. Instead, it's resolved relative
+//! to the CSS file it's written in, copied to `build/assets` with a hash of its contents in the
+//! name, and the `url()` is rewritten to the copy.
+
+use crate::css::{unescape_identifier, CssNode};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Where assets are copied to, relative to the build.
+pub const ASSETS_DIRECTORY: &str = "assets";
+
+/// The assets which have been copied so far, so every asset is only copied once.
+#[derive(Default)]
+pub struct Assets {
+    copied: HashMap<PathBuf, Option<String>>,
+}
+
+impl Assets {
+    /// Rewrites every `url()` in the CSS, which was read from the given file.
+    pub fn rewrite_urls(&mut self, nodes: &mut [CssNode], css_path: &Path) {
+        rewrite_urls_with(nodes, &mut |url| self.resolve(url, css_path));
+    }
+
+    /// The URL of the copy of what a URL points to, if it points to a file next to the CSS.
+    fn resolve(&mut self, url: &str, css_path: &Path) -> Option<String> {
+        if !is_relative_url(url) {
+            return None;
+        }
+
+        // `icons.svg#star` and `font.woff2?v=2` refer to `icons.svg` and `font.woff2`
+        let (file, suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+        let path = css_path.parent().unwrap_or(Path::new("")).join(file);
+
+        let copy = self.copy(&path, css_path)?;
+        Some(copy + suffix)
+    }
+
+    /// Copies a file into the assets, returning its URL relative to the build.
+    fn copy(&mut self, path: &Path, css_path: &Path) -> Option<String> {
+        let path = match path.canonicalize() {
+            Ok(path) => path,
+            // only warned about once, even if it's used on every page
+            Err(_) if self.copied.contains_key(path) => return None,
+            Err(_) => {
+                println!(
+                    "[WARN] '{}' refers to '{}', which doesn't exist",
+                    css_path.display(),
+                    path.display()
+                );
+                self.copied.insert(path.to_owned(), None);
+                return None;
+            }
+        };
+
+        if let Some(url) = self.copied.get(&path) {
+            return url.clone();
+        }
+
+        let url = copy_asset(&path);
+        self.copied.insert(path, url.clone());
+        url
+    }
+}
+
+/// Makes every relative `url()` in CSS that's moved into another file relative to the new file
+/// instead, e.g. `url(logo.png)` in `partials/_header.css` is `url(partials/logo.png)` once it's
+/// imported by `Header.css`.
+pub fn rebase_urls(nodes: &mut [CssNode], directory: &Path) {
+    rewrite_urls_with(nodes, &mut |url| {
+        if !is_relative_url(url) {
+            return None;
+        }
+
+        directory
+            .join(url)
+            .to_str()
+            .map(|url| url.replace('\\', "/"))
+    });
+}
+
+/// Whether a URL is relative to the CSS it's in. `data:`, `https://`, `/root-relative` and
+/// `#fragment` URLs aren't.
+pub fn is_relative_url(url: &str) -> bool {
+    !url.is_empty()
+        && !url.starts_with(['/', '#'])
+        && !url
+            .split(['/', '?', '#'])
+            .next()
+            .is_some_and(|scheme| scheme.contains(':'))
+}
+
+fn rewrite_urls_with(nodes: &mut [CssNode], rewrite: &mut dyn FnMut(&str) -> Option<String>) {
+    for node in nodes.iter_mut() {
+        match node {
+            CssNode::Declaration { value, .. } => {
+                if let Some(rewritten) = rewrite_value(value, rewrite) {
+                    *value = rewritten;
+                }
+            }
+            CssNode::Rule { children, .. }
+            | CssNode::Keyframe { children, .. }
+            | CssNode::AtRule {
+                children: Some(children),
+                ..
+            } => rewrite_urls_with(children, rewrite),
+            _ => (),
+        }
+    }
+}
+
+/// Rewrites the `url()`s in a value, if any of them are rewritten at all.
+pub fn rewrite_value(
+    value: &str,
+    rewrite: &mut dyn FnMut(&str) -> Option<String>,
+) -> Option<String> {
+    let mut output = String::with_capacity(value.len());
+    let mut rest = value;
+    let mut rewritten = false;
+
+    while let Some(character) = rest.chars().next() {
+        let length = match character {
+            '"' | '\'' => string_length(rest, character),
+            _ if starts_url(rest) && !output.ends_with(is_ident_character) => {
+                let (url, length) = match parse_url(rest) {
+                    Some(url) => url,
+                    None => {
+                        output.push_str(rest);
+                        break;
+                    }
+                };
+
+                // the url is given to the rewrite without the escapes of its quotes
+                match rewrite(&unescape_identifier(url)) {
+                    Some(url) => {
+                        write_url(&mut output, &url);
+                        rewritten = true;
+                    }
+                    None => output.push_str(&rest[..length]),
+                }
+
+                rest = &rest[length..];
+                continue;
+            }
+            _ => character.len_utf8(),
+        };
+
+        output.push_str(&rest[..length]);
+        rest = &rest[length..];
+    }
+
+    if rewritten {
+        Some(output)
+    } else {
+        None
+    }
+}
+
+/// Copies a file into the assets with a hash of its contents in its name, e.g. `hero.jpg` as
+/// `hero.1a2b3c4d.jpg`.
+fn copy_asset(path: &Path) -> Option<String> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(_) => {
+            println!("[WARN] couldn't read asset '{}'", path.display());
+            return None;
+        }
+    };
+
+    let hash = format!("{:x}", Sha256::digest(&data));
+    let stem = path.file_stem()?.to_str()?;
+    let name = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => format!("{}.{}.{}", stem, &hash[..8], extension),
+        None => format!("{}.{}", stem, &hash[..8]),
+    };
+
+    let directory = Path::new("build").join(ASSETS_DIRECTORY);
+    let written = std::fs::create_dir_all(&directory)
+        .and_then(|_| std::fs::write(directory.join(&name), &data));
+
+    if written.is_err() {
+        println!("[ERR] couldn't copy asset '{}'", path.display());
+        return None;
+    }
+
+    Some(format!("{}/{}", ASSETS_DIRECTORY, name))
+}
+
+#[inline]
+fn starts_url(text: &str) -> bool {
+    text.get(..4)
+        .is_some_and(|start| start.eq_ignore_ascii_case("url("))
+}
+
+/// Reads the URL out of a `url(...)`, along with how long the whole `url(...)` is.
+pub fn parse_url(text: &str) -> Option<(&str, usize)> {
+    if !starts_url(text) {
+        return None;
+    }
+
+    let inside = &text[4..];
+    let start = inside.len() - inside.trim_start().len();
+    let inside = &inside[start..];
+
+    let (url, end) = match inside.chars().next() {
+        Some(quote) if quote == '"' || quote == '\'' => {
+            let length = string_length(inside, quote);
+            let url = inside.get(1..length - 1)?;
+            let end = length + inside[length..].find(')')?;
+            (url, end)
+        }
+        _ => {
+            let end = inside.find(')')?;
+            (inside[..end].trim(), end)
+        }
+    };
+
+    Some((url, 4 + start + end + 1))
+}
+
+/// Writes a `url()`, only quoting it if it has to be.
+fn write_url(output: &mut String, url: &str) {
+    let needs_quotes = url
+        .chars()
+        .any(|character| character.is_whitespace() || "()'\"\\".contains(character));
+
+    if needs_quotes {
+        output.push_str("url(\"");
+        output.push_str(&url.replace('\\', "\\\\").replace('"', "\\\""));
+        output.push_str("\")");
+    } else {
+        output.push_str("url(");
+        output.push_str(url);
+        output.push(')');
+    }
+}
+
+/// The length of the string at the start of the text, quotes included.
+fn string_length(text: &str, quote: char) -> usize {
+    let mut escaped = false;
+
+    for (index, character) in text.char_indices().skip(1) {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if character == quote => return index + 1,
+            _ => (),
+        }
+    }
+
+    text.len()
+}
+
+#[inline]
+fn is_ident_character(character: char) -> bool {
+    character.is_ascii_alphanumeric() || matches!(character, '-' | '_') || !character.is_ascii()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css::Stylesheet;
+
+    fn rewritten(value: &str) -> Option<String> {
+        rewrite_value(value, &mut |url| Some(format!("images/{}", url)))
+    }
+
+    #[test]
+    fn only_relative_urls_are_relative() {
+        assert!(is_relative_url("logo.png"));
+        assert!(is_relative_url("../fonts/inter.woff2?v=2"));
+        assert!(!is_relative_url("data:image/png;base64,AAAA"));
+        assert!(!is_relative_url("https://example.com/logo.png"));
+        assert!(!is_relative_url("//cdn.example.com/logo.png"));
+        assert!(!is_relative_url("/root.png"));
+        assert!(!is_relative_url("#gradient"));
+        assert!(!is_relative_url(""));
+    }
+
+    #[test]
+    fn rewrites_quoted_and_escaped_urls() {
+        assert_eq!(
+            rewritten("url(a.png) no-repeat, url( 'b.png' )"),
+            Some("url(images/a.png) no-repeat, url(images/b.png)".to_owned())
+        );
+        assert_eq!(
+            rewritten("url(\"my logo.png\")"),
+            Some("url(\"images/my logo.png\")".to_owned())
+        );
+        assert_eq!(
+            rewritten("url('it\\'s.png')"),
+            Some("url(\"images/it's.png\")".to_owned())
+        );
+        assert_eq!(rewritten("\"url(a.png)\" myurl(a.png)"), None);
+    }
+
+    #[test]
+    fn rebases_relative_urls() {
+        let mut stylesheet = Stylesheet::parse(
+            ".a { background: url(logo.png), url(/root.png), url(data:image/png;base64,AA) }",
+        )
+        .unwrap();
+        rebase_urls(&mut stylesheet.nodes, Path::new("partials"));
+
+        assert_eq!(
+            stylesheet.write(true),
+            ".a{background:url(partials/logo.png), url(/root.png), url(data:image/png;base64,AA)}"
+        );
+    }
+
+    #[test]
+    fn keeps_the_query_and_fragment_of_assets() {
+        let directory = std::env::temp_dir().join("goodweb-assets");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("icons.svg"), "<svg/>").unwrap();
+        let css_path = directory.join("Button.css");
+
+        // already copied, so nothing is written to the build
+        let mut assets = Assets::default();
+        assets.copied.insert(
+            directory.join("icons.svg").canonicalize().unwrap(),
+            Some("assets/icons.1a2b3c4d.svg".to_owned()),
+        );
+
+        assert_eq!(
+            assets.resolve("icons.svg#star", &css_path),
+            Some("assets/icons.1a2b3c4d.svg#star".to_owned())
+        );
+        assert_eq!(
+            assets.resolve("icons.svg?v=2", &css_path),
+            Some("assets/icons.1a2b3c4d.svg?v=2".to_owned())
+        );
+        assert_eq!(assets.resolve("#star", &css_path), None);
+    }
+
+    #[test]
+    fn missing_assets_are_only_warned_about_once() {
+        let css_path = std::env::temp_dir()
+            .join("goodweb-missing")
+            .join("Button.css");
+        let mut assets = Assets::default();
+
+        assert_eq!(assets.resolve("missing.png", &css_path), None);
+        assert_eq!(assets.resolve("missing.png?v=2", &css_path), None);
+        assert_eq!(assets.copied.len(), 1);
+    }
+}
@@ -0,0 +1,261 @@
+//! Loads the CSS of a component, inlining the partials it `@import`s and flattening nested rules.
+//!
+//! Partials are plain CSS files, and are looked up relative to the file importing them. A partial
+//! in the components directory should start with a `_`, like `_buttons.css`, so that it isn't
+//! mistaken for the CSS of a component.
+
+use crate::css::{CssNode, ParseError, Stylesheet};
+use crate::css_assets::{self, is_relative_url};
+use crate::css_nesting;
+use std::path::{Path, PathBuf};
+
+/// Parses the CSS read from a file. Errors are reported against the file, or against the partial
+/// they're in.
+pub fn load_stylesheet(data: &str, path: &Path) -> Result<Stylesheet, ParseError> {
+    let mut importing = vec![canonical(path)];
+    let mut nodes = load_nodes(data, path, &mut importing)?;
+    hoist_imports(&mut nodes);
+
+    let mut stylesheet = Stylesheet { nodes };
+    css_nesting::flatten(&mut stylesheet);
+    Ok(stylesheet)
+}
+
+/// Moves every `@charset` and `@import` to the front, in order, since they're ignored anywhere
+/// else. That's the imports left after inlining partials, like `@import url(https://...)`, and
+/// the ones of every stylesheet joined into one. Only the first `@charset` is kept.
+pub fn hoist_imports(nodes: &mut Vec<CssNode>) {
+    let mut charset = None;
+    let mut imports = Vec::new();
+    let mut rest = Vec::with_capacity(nodes.len());
+
+    for node in nodes.drain(..) {
+        match &node {
+            CssNode::AtRule { name, .. } if name == "charset" => {
+                charset.get_or_insert(node);
+            }
+            CssNode::AtRule { name, .. } if name == "import" => imports.push(node),
+            _ => rest.push(node),
+        }
+    }
+
+    nodes.extend(charset);
+    nodes.extend(imports);
+    nodes.extend(rest);
+}
+
+fn load_nodes(
+    data: &str,
+    path: &Path,
+    importing: &mut Vec<PathBuf>,
+) -> Result<Vec<CssNode>, ParseError> {
+    let stylesheet = Stylesheet::parse(data).map_err(|error| error.in_file(path))?;
+    let mut nodes = Vec::with_capacity(stylesheet.nodes.len());
+
+    for node in stylesheet.nodes {
+        let imported = match &node {
+            CssNode::AtRule {
+                name,
+                prelude,
+                children: None,
+            } if name == "import" => import(prelude, path, importing)?,
+            _ => None,
+        };
+
+        match imported {
+            Some(imported) => nodes.extend(imported),
+            None => nodes.push(node),
+        }
+    }
+
+    Ok(nodes)
+}
+
+/// Reads the partial an `@import` refers to. Imports which aren't of a partial, like
+/// `@import url(https://...)`, are left alone.
+fn import(
+    prelude: &str,
+    path: &Path,
+    importing: &mut Vec<PathBuf>,
+) -> Result<Option<Vec<CssNode>>, ParseError> {
+    let (url, media) = match parse_import(prelude) {
+        Some(import) => import,
+        None => return Ok(None),
+    };
+
+    // `layer(...)` and `supports(...)` can't be written without an @import
+    if !is_relative_url(url) || media.starts_with("layer") || media.starts_with("supports(") {
+        return Ok(None);
+    }
+
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let import_path = directory.join(url);
+
+    let data = match std::fs::read_to_string(&import_path) {
+        Ok(data) => data,
+        Err(_) => {
+            println!(
+                "[WARN] '{}' imports '{}', which couldn't be read",
+                path.display(),
+                import_path.display()
+            );
+            return Ok(None);
+        }
+    };
+
+    let canonical_path = canonical(&import_path);
+    if importing.contains(&canonical_path) {
+        println!(
+            "[WARN] '{}' imports '{}', which is already being imported",
+            path.display(),
+            import_path.display()
+        );
+        return Ok(Some(Vec::new()));
+    }
+
+    importing.push(canonical_path);
+    let mut nodes = load_nodes(&data, &import_path, importing)?;
+    importing.pop();
+
+    // the partial's urls are relative to the partial, not the file importing it
+    if let Some(url_directory) = Path::new(url).parent() {
+        if !url_directory.as_os_str().is_empty() {
+            css_assets::rebase_urls(&mut nodes, url_directory);
+        }
+    }
+
+    if media.is_empty() {
+        return Ok(Some(nodes));
+    }
+
+    Ok(Some(vec![CssNode::AtRule {
+        name: String::from("media"),
+        prelude: media.to_owned(),
+        children: Some(nodes),
+    }]))
+}
+
+/// Splits `"file.css" screen` into the URL and the media queries after it.
+fn parse_import(prelude: &str) -> Option<(&str, &str)> {
+    let prelude = prelude.trim();
+
+    let (url, length) = match prelude.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let end = prelude[1..].find(quote)? + 1;
+            (&prelude[1..end], end + 1)
+        }
+        _ => css_assets::parse_url(prelude)?,
+    };
+
+    Some((url, prelude[length..].trim()))
+}
+
+#[inline]
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // writes the files into a directory of their own, and loads the first one
+    fn load(name: &str, files: &[(&str, &str)]) -> String {
+        let directory = std::env::temp_dir().join(format!("goodweb-imports-{}", name));
+        let _ = std::fs::remove_dir_all(&directory);
+
+        for (file, data) in files {
+            let path = directory.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, data).unwrap();
+        }
+
+        let (file, data) = files[0];
+        load_stylesheet(data, &directory.join(file))
+            .unwrap()
+            .write(true)
+    }
+
+    #[test]
+    fn splits_the_url_from_the_media() {
+        assert_eq!(parse_import("\"a.css\""), Some(("a.css", "")));
+        assert_eq!(parse_import("'a.css' screen"), Some(("a.css", "screen")));
+        assert_eq!(
+            parse_import("url(a.css) print and (min-width: 40em)"),
+            Some(("a.css", "print and (min-width: 40em)"))
+        );
+        assert_eq!(parse_import("a.css"), None);
+    }
+
+    #[test]
+    fn inlines_partials() {
+        assert_eq!(
+            load(
+                "partials",
+                &[
+                    ("Card.css", "@import \"_base.css\";\n@import \"_print.css\" print;\n.card { color: red }"),
+                    ("_base.css", ".base { margin: 0 }"),
+                    ("_print.css", ".card { color: black }"),
+                ]
+            ),
+            ".base{margin:0}@media print{.card{color:black}}.card{color:red}"
+        );
+    }
+
+    #[test]
+    fn leaves_other_imports_alone_and_moves_them_first() {
+        assert_eq!(
+            load(
+                "other",
+                &[
+                    ("Card.css", ".card { color: red }\n@import \"_base.css\" layer(base);\n@import \"_base.css\" supports(display: grid);\n@import url(https://example.com/font.css);\n@charset \"utf-8\";"),
+                    ("_base.css", ".base { margin: 0 }"),
+                ]
+            ),
+            "@charset \"utf-8\";@import \"_base.css\" layer(base);@import \"_base.css\" supports(display: grid);@import url(https://example.com/font.css);.card{color:red}"
+        );
+    }
+
+    #[test]
+    fn rebases_the_urls_of_partials_in_other_directories() {
+        assert_eq!(
+            load(
+                "rebase",
+                &[
+                    ("Card.css", "@import \"partials/_hero.css\";"),
+                    ("partials/_hero.css", ".hero { background: url(hero.jpg) }"),
+                ]
+            ),
+            ".hero{background:url(partials/hero.jpg)}"
+        );
+    }
+
+    #[test]
+    fn stops_at_import_cycles() {
+        assert_eq!(
+            load(
+                "cycle",
+                &[
+                    ("Card.css", "@import \"_a.css\";"),
+                    ("_a.css", "@import \"_b.css\";\n.a { color: red }"),
+                    ("_b.css", "@import \"_a.css\";\n.b { color: blue }"),
+                ]
+            ),
+            ".b{color:blue}.a{color:red}"
+        );
+    }
+
+    #[test]
+    fn keeps_only_the_first_charset() {
+        let mut stylesheet = Stylesheet::parse(
+            "@charset \"utf-8\";\n.a { color: red }\n@charset \"utf-8\";\n@import url(b.css);",
+        )
+        .unwrap();
+        hoist_imports(&mut stylesheet.nodes);
+
+        assert_eq!(
+            stylesheet.write(true),
+            "@charset \"utf-8\";@import url(b.css);.a{color:red}"
+        );
+    }
+}
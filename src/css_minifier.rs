@@ -0,0 +1,623 @@
+//! Makes stylesheets as small as possible, without changing what they do.
+//!
+//! Along with writing the stylesheet without whitespace (see `Stylesheet::write`), this:
+//! - writes numbers and colours in their shortest form, e.g. `0.50` as `.5` and `#ffffff` as `#fff`
+//! - removes declarations which are repeated exactly, and rules which are empty
+//! - merges rules next to each other which have the same selectors, or the same declarations
+
+use crate::css::{parse_selector, CssNode, Selector, SimpleSelector, Stylesheet};
+
+pub fn minify(stylesheet: &mut Stylesheet) {
+    minify_nodes(&mut stylesheet.nodes);
+}
+
+fn minify_nodes(nodes: &mut Vec<CssNode>) {
+    for node in nodes.iter_mut() {
+        match node {
+            CssNode::Declaration { property, value } => {
+                // custom properties can hold anything, so they're left alone
+                if !property.starts_with("--") {
+                    *value = minify_value(value, is_color_property(property));
+                }
+            }
+            CssNode::Rule { children, .. } => minify_nodes(children),
+            CssNode::Keyframe { selector, children } => {
+                *selector = match selector.as_str() {
+                    "from" => "0%".to_owned(),
+                    "100%" => "to".to_owned(),
+                    _ => selector.clone(),
+                };
+
+                minify_nodes(children);
+            }
+            CssNode::AtRule {
+                name,
+                prelude,
+                children,
+            } => {
+                // the conditions of @media and @supports are written like values
+                if matches!(name.as_str(), "media" | "supports") {
+                    *prelude = minify_value(prelude, false);
+                }
+
+                if let Some(children) = children {
+                    minify_nodes(children);
+                }
+            }
+            CssNode::Comment(_) => (),
+        }
+    }
+
+    remove_repeated_declarations(nodes);
+    merge_rules(nodes);
+    nodes.retain(|node| !is_empty(node));
+}
+
+/// Removes declarations which are exactly the same as a later one. Declarations of the same
+/// property with different values are kept, since they're commonly used as fallbacks.
+fn remove_repeated_declarations(nodes: &mut Vec<CssNode>) {
+    let mut index = 0;
+
+    while index < nodes.len() {
+        let is_repeated = match &nodes[index] {
+            CssNode::Declaration { .. } => nodes[index + 1..].contains(&nodes[index]),
+            _ => false,
+        };
+
+        if is_repeated {
+            nodes.remove(index);
+        } else {
+            index += 1;
+        }
+    }
+}
+
+/// Merges rules which are next to each other, so `.a{color:red}.a{margin:0}` turns into
+/// `.a{color:red;margin:0}`, and `.a{color:red}.b{color:red}` into `.a,.b{color:red}`. Rules that
+/// aren't next to each other are left alone, since moving them could change the cascade.
+fn merge_rules(nodes: &mut Vec<CssNode>) {
+    let mut merged: Vec<CssNode> = Vec::with_capacity(nodes.len());
+
+    for node in nodes.drain(..) {
+        if let (
+            Some(CssNode::Rule {
+                selectors: previous_selectors,
+                children: previous_children,
+            }),
+            CssNode::Rule {
+                selectors,
+                children,
+            },
+        ) = (merged.last_mut(), &node)
+        {
+            if previous_selectors == selectors {
+                previous_children.extend(children.iter().cloned());
+                remove_repeated_declarations(previous_children);
+                continue;
+            }
+
+            // a browser drops a whole rule if it doesn't understand one of its selectors, so
+            // selectors that not every browser knows are never merged with anything else
+            if previous_children == children
+                && previous_selectors
+                    .iter()
+                    .chain(selectors.iter())
+                    .all(is_supported_everywhere)
+            {
+                for selector in selectors.iter() {
+                    if !previous_selectors.contains(selector) {
+                        previous_selectors.push(selector.clone());
+                    }
+                }
+
+                continue;
+            }
+        }
+
+        merged.push(node);
+    }
+
+    *nodes = merged;
+}
+
+/// Whether every browser understands a selector, i.e. it only uses what's in CSS 2 and Selectors
+/// Level 3. Anything newer, like `:focus-visible`, `:has()` or vendor prefixes, may be unknown.
+fn is_supported_everywhere(selector: &Selector) -> bool {
+    selector
+        .compounds
+        .iter()
+        .flat_map(|compound| compound.parts.iter())
+        .all(|part| match part {
+            SimpleSelector::PseudoClass(name, arguments) => {
+                is_supported_pseudo_class(name, arguments.as_deref())
+            }
+            SimpleSelector::PseudoElement(name, None) => matches!(
+                name.as_str(),
+                "before" | "after" | "first-line" | "first-letter"
+            ),
+            SimpleSelector::PseudoElement(_, Some(_)) => false,
+            _ => true,
+        })
+}
+
+fn is_supported_pseudo_class(name: &str, arguments: Option<&str>) -> bool {
+    let arguments = match arguments {
+        Some(arguments) => arguments,
+        None => {
+            return matches!(
+                name,
+                "active"
+                    | "after"
+                    | "before"
+                    | "checked"
+                    | "disabled"
+                    | "empty"
+                    | "enabled"
+                    | "first-child"
+                    | "first-letter"
+                    | "first-line"
+                    | "first-of-type"
+                    | "focus"
+                    | "hover"
+                    | "last-child"
+                    | "last-of-type"
+                    | "link"
+                    | "only-child"
+                    | "only-of-type"
+                    | "root"
+                    | "target"
+                    | "visited"
+            )
+        }
+    };
+
+    match name {
+        "lang" | "nth-child" | "nth-last-child" | "nth-of-type" | "nth-last-of-type" => {
+            // `:nth-child(2 of .a)` is newer
+            !arguments.contains(" of ")
+        }
+        // `:not()` used to only take one simple selector
+        "not" => parse_selector(arguments).is_some_and(|selector| {
+            selector.compounds.len() == 1
+                && selector.compounds[0].parts.len() == 1
+                && is_supported_everywhere(&selector)
+                && !matches!(
+                    selector.compounds[0].parts[0],
+                    SimpleSelector::PseudoClass(ref name, _) if name == "not"
+                )
+        }),
+        _ => false,
+    }
+}
+
+#[inline]
+fn is_empty(node: &CssNode) -> bool {
+    match node {
+        CssNode::Rule { children, .. } | CssNode::Keyframe { children, .. } => children.is_empty(),
+        // @font-face {} does nothing, but an empty @layer {} still decides the order of layers
+        CssNode::AtRule {
+            name,
+            children: Some(children),
+            ..
+        } => children.is_empty() && matches!(name.as_str(), "media" | "supports" | "font-face"),
+        _ => false,
+    }
+}
+
+/// Removes unnecessary whitespace from a value, and writes its numbers and colours in their
+/// shortest form. Strings and `url()`s are left exactly as they are.
+fn minify_value(value: &str, is_color: bool) -> String {
+    let mut output = String::with_capacity(value.len());
+    let mut rest = value;
+    let mut pending_space = false;
+
+    while let Some(character) = rest.chars().next() {
+        if character.is_whitespace() {
+            pending_space = true;
+            rest = &rest[character.len_utf8()..];
+            continue;
+        }
+
+        // whitespace is only needed between things that would otherwise run together
+        if pending_space {
+            pending_space = false;
+
+            let after_separator = output.ends_with([',', '(', ':']);
+            let before_separator = matches!(character, ',' | ')' | '!' | ':');
+
+            if !output.is_empty() && !after_separator && !before_separator {
+                output.push(' ');
+            }
+        }
+
+        let length = match character {
+            '"' | '\'' => string_length(rest, character),
+            _ if starts_with_ignore_case(rest, "url(") => {
+                rest.find(')').map_or(rest.len(), |end| end + 1)
+            }
+            // unicode ranges like `U+0000-00FF` look like numbers, but aren't
+            'u' | 'U' if rest[1..].starts_with('+') && !output.ends_with(is_ident_character) => {
+                2 + rest[2..]
+                    .find(|character: char| {
+                        !character.is_ascii_hexdigit() && !matches!(character, '?' | '-')
+                    })
+                    .unwrap_or(rest.len() - 2)
+            }
+            '#' => {
+                let length = 1 + ident_length(&rest[1..]);
+                output.push_str(&minify_hex(&rest[..length], is_color));
+                rest = &rest[length..];
+                continue;
+            }
+            _ if starts_number(rest) && !output.ends_with(is_ident_character) => {
+                let length = number_length(rest);
+                output.push_str(&minify_number(&rest[..length]));
+                rest = &rest[length..];
+                continue;
+            }
+            _ if is_ident_character(character) => {
+                let length = ident_length(rest);
+                let ident = &rest[..length];
+
+                match shorter_color(ident, is_color) {
+                    Some(color) => output.push_str(color),
+                    None => output.push_str(ident),
+                }
+
+                rest = &rest[length..];
+                continue;
+            }
+            _ => character.len_utf8(),
+        };
+
+        output.push_str(&rest[..length]);
+        rest = &rest[length..];
+    }
+
+    output
+}
+
+#[inline]
+fn is_ident_character(character: char) -> bool {
+    character.is_ascii_alphanumeric()
+        || matches!(character, '-' | '_' | '\\')
+        || !character.is_ascii()
+}
+
+#[inline]
+fn ident_length(text: &str) -> usize {
+    text.find(|character| !is_ident_character(character))
+        .unwrap_or(text.len())
+}
+
+#[inline]
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+/// The length of a quoted string, quotes included.
+fn string_length(text: &str, quote: char) -> usize {
+    let mut escaped = false;
+
+    for (index, character) in text.char_indices().skip(1) {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if character == quote => return index + 1,
+            _ => (),
+        }
+    }
+
+    text.len()
+}
+
+/// Whether the text starts with a number, like `1`, `.5`, `-2px` or `+.5em`.
+fn starts_number(text: &str) -> bool {
+    let unsigned = text
+        .strip_prefix(|sign| sign == '-' || sign == '+')
+        .unwrap_or(text);
+    let mut characters = unsigned.chars();
+
+    match characters.next() {
+        Some(digit) if digit.is_ascii_digit() => true,
+        Some('.') => characters
+            .next()
+            .is_some_and(|digit| digit.is_ascii_digit()),
+        _ => false,
+    }
+}
+
+/// The length of a number along with its unit, e.g. `-1.50em`.
+fn number_length(text: &str) -> usize {
+    let mut length = 0;
+    let bytes = text.as_bytes();
+
+    if matches!(bytes.first(), Some(b'-') | Some(b'+')) {
+        length += 1;
+    }
+
+    while length < bytes.len() && (bytes[length].is_ascii_digit() || bytes[length] == b'.') {
+        length += 1;
+    }
+
+    // exponents, but not units that start with an `e` like `em`
+    if length < bytes.len() && matches!(bytes[length], b'e' | b'E') {
+        let exponent = &text[length + 1..];
+        let exponent = exponent
+            .strip_prefix(|sign| sign == '-' || sign == '+')
+            .unwrap_or(exponent);
+
+        if exponent.starts_with(|digit: char| digit.is_ascii_digit()) {
+            length = text.len() - exponent.len();
+            length += exponent
+                .find(|digit: char| !digit.is_ascii_digit())
+                .unwrap_or(exponent.len());
+        }
+    }
+
+    // the unit, or a percentage
+    if text[length..].starts_with('%') {
+        return length + 1;
+    }
+
+    length + ident_length(&text[length..])
+}
+
+/// `0.50em` turns into `.5em`, `1.0` into `1` and `-0.5` into `-.5`.
+fn minify_number(number: &str) -> String {
+    let unit_start = number
+        .find(|character: char| {
+            !(character.is_ascii_digit() || matches!(character, '.' | '-' | '+'))
+        })
+        .unwrap_or(number.len());
+
+    let (numeric, unit) = number.split_at(unit_start);
+
+    // exponents are rare enough to not bother with
+    if unit.starts_with(['e', 'E'])
+        && unit[1..]
+            .starts_with(|digit: char| digit.is_ascii_digit() || digit == '-' || digit == '+')
+    {
+        return number.to_owned();
+    }
+
+    let (sign, digits) = match numeric.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", numeric.strip_prefix('+').unwrap_or(numeric)),
+    };
+
+    let (integer, fraction) = match digits.find('.') {
+        Some(dot) => (&digits[..dot], digits[dot + 1..].trim_end_matches('0')),
+        None => (digits, ""),
+    };
+
+    let integer = integer.trim_start_matches('0');
+
+    let mut minified = String::with_capacity(number.len());
+
+    if integer.is_empty() && fraction.is_empty() {
+        minified.push('0');
+    } else {
+        minified.push_str(sign);
+        minified.push_str(integer);
+
+        if !fraction.is_empty() {
+            minified.push('.');
+            minified.push_str(fraction);
+        }
+    }
+
+    minified.push_str(unit);
+    minified
+}
+
+/// `#FFFFFF` turns into `#fff`, and `#ff0000` into `red` where colours are expected.
+fn minify_hex(hex: &str, is_color: bool) -> String {
+    let digits = &hex[1..];
+
+    if !matches!(digits.len(), 3 | 4 | 6 | 8)
+        || !digits.chars().all(|digit| digit.is_ascii_hexdigit())
+    {
+        return hex.to_owned();
+    }
+
+    let mut digits = digits.to_ascii_lowercase();
+    let bytes = digits.as_bytes();
+
+    // #aabbcc turns into #abc, and #aabbccdd into #abcd
+    if matches!(bytes.len(), 6 | 8) && bytes.chunks(2).all(|pair| pair[0] == pair[1]) {
+        digits = bytes
+            .chunks(2)
+            .map(|pair| pair[0] as char)
+            .collect::<String>();
+    }
+
+    let mut minified = String::with_capacity(digits.len() + 1);
+    minified.push('#');
+    minified.push_str(&digits);
+
+    if is_color {
+        if let Some((name, _)) = COLORS.iter().find(|(_, color)| *color == minified) {
+            if name.len() < minified.len() {
+                return (*name).to_owned();
+            }
+        }
+    }
+
+    minified
+}
+
+/// `white` turns into `#fff` where colours are expected.
+fn shorter_color(ident: &str, is_color: bool) -> Option<&'static str> {
+    if !is_color {
+        return None;
+    }
+
+    COLORS
+        .iter()
+        .find(|(name, _)| ident.eq_ignore_ascii_case(name))
+        .map(|(_, color)| *color)
+        .filter(|color| color.len() < ident.len())
+}
+
+/// Only properties which hold colours have their colours changed, so a font named `Tan` stays.
+#[inline]
+fn is_color_property(property: &str) -> bool {
+    property.contains("color")
+        || matches!(
+            property,
+            "background"
+                | "border"
+                | "border-top"
+                | "border-right"
+                | "border-bottom"
+                | "border-left"
+                | "box-shadow"
+                | "column-rule"
+                | "fill"
+                | "outline"
+                | "stroke"
+                | "text-decoration"
+                | "text-shadow"
+        )
+}
+
+/// Colour names, along with their shortest hex form.
+const COLORS: &[(&str, &str)] = &[
+    ("aqua", "#0ff"),
+    ("azure", "#f0ffff"),
+    ("beige", "#f5f5dc"),
+    ("bisque", "#ffe4c4"),
+    ("black", "#000"),
+    ("blue", "#00f"),
+    ("brown", "#a52a2a"),
+    ("coral", "#ff7f50"),
+    ("cyan", "#0ff"),
+    ("fuchsia", "#f0f"),
+    ("gold", "#ffd700"),
+    ("gray", "#808080"),
+    ("green", "#008000"),
+    ("grey", "#808080"),
+    ("indigo", "#4b0082"),
+    ("ivory", "#fffff0"),
+    ("khaki", "#f0e68c"),
+    ("lime", "#0f0"),
+    ("linen", "#faf0e6"),
+    ("magenta", "#f0f"),
+    ("maroon", "#800000"),
+    ("navy", "#000080"),
+    ("olive", "#808000"),
+    ("orange", "#ffa500"),
+    ("orchid", "#da70d6"),
+    ("peru", "#cd853f"),
+    ("pink", "#ffc0cb"),
+    ("plum", "#dda0dd"),
+    ("purple", "#800080"),
+    ("red", "#f00"),
+    ("salmon", "#fa8072"),
+    ("sienna", "#a0522d"),
+    ("silver", "#c0c0c0"),
+    ("snow", "#fffafa"),
+    ("tan", "#d2b48c"),
+    ("teal", "#008080"),
+    ("tomato", "#ff6347"),
+    ("violet", "#ee82ee"),
+    ("wheat", "#f5deb3"),
+    ("white", "#fff"),
+    ("yellow", "#ff0"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minified(css: &str) -> String {
+        let mut stylesheet = Stylesheet::parse(css).unwrap();
+        minify(&mut stylesheet);
+        stylesheet.write(true)
+    }
+
+    #[test]
+    fn shortens_numbers_and_colours() {
+        assert_eq!(
+            minified(
+                ".a { opacity: 0.50; margin: 0px 1.0em; color: #ffffff; background: #AABBCC }"
+            ),
+            ".a{opacity:.5;margin:0px 1em;color:#fff;background:#abc}"
+        );
+    }
+
+    #[test]
+    fn leaves_custom_properties_and_strings_alone() {
+        assert_eq!(
+            minified(".a { --space: 0.50px; content: \"0.50 #ffffff\" }"),
+            ".a{--space:0.50px;content:\"0.50 #ffffff\"}"
+        );
+    }
+
+    #[test]
+    fn leaves_unicode_ranges_alone() {
+        assert_eq!(
+            minified("@font-face { unicode-range: U+0000-00FF, U+0131, u+4??; }"),
+            "@font-face{unicode-range:U+0000-00FF,U+0131,u+4??}"
+        );
+    }
+
+    #[test]
+    fn removes_repeats_and_empty_rules() {
+        assert_eq!(
+            minified(".a { color: red; color: red } .b {} @media print {}"),
+            ".a{color:red}"
+        );
+        // different values are fallbacks
+        assert_eq!(
+            minified(".a { display: block; display: grid }"),
+            ".a{display:block;display:grid}"
+        );
+    }
+
+    #[test]
+    fn merges_neighbouring_rules() {
+        assert_eq!(
+            minified(".a { color: red } .a { margin: 0 } .b { margin: 0 }"),
+            ".a{color:red;margin:0}.b{margin:0}"
+        );
+        assert_eq!(
+            minified(".a { color: red } .b { color: red }"),
+            ".a,.b{color:red}"
+        );
+    }
+
+    #[test]
+    fn shortens_keyframes() {
+        assert_eq!(
+            minified("@keyframes x { from { opacity: 0 } 100% { opacity: 1 } }"),
+            "@keyframes x{0%{opacity:0}to{opacity:1}}"
+        );
+    }
+
+    #[test]
+    fn doesnt_merge_newer_selectors() {
+        assert_eq!(
+            minified(".c:focus-visible { color: red } .d { color: red }"),
+            ".c:focus-visible{color:red}.d{color:red}"
+        );
+        assert_eq!(
+            minified(".a:has(img) { color: red } .b:not(.c .d) { color: red }"),
+            ".a:has(img){color:red}.b:not(.c .d){color:red}"
+        );
+        assert_eq!(
+            minified("a:hover { color: red } li:nth-child(2n+1):not(.x) { color: red }"),
+            "a:hover,li:nth-child(2n+1):not(.x){color:red}"
+        );
+    }
+
+    #[test]
+    fn doesnt_merge_vendor_prefixes() {
+        assert_eq!(
+            minified("::-moz-selection { color: red } ::selection { color: red }"),
+            "::-moz-selection{color:red}::selection{color:red}"
+        );
+    }
+}
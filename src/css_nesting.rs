@@ -0,0 +1,234 @@
+//! Flattens nested rules, so that browsers which don't know about CSS nesting understand them.
+//!
+//! `.card { color: red; .title { ... } &:hover { ... } }` turns into `.card { color: red; }`,
+//! `.card .title { ... }` and `.card:hover { ... }`. At-rules inside of a rule, like `@media`,
+//! are moved outside of it, with the rule inside of them.
+
+use crate::css::{Combinator, Compound, CssNode, Selector, SimpleSelector, Stylesheet};
+
+pub fn flatten(stylesheet: &mut Stylesheet) {
+    let nodes = std::mem::take(&mut stylesheet.nodes);
+    stylesheet.nodes = flatten_nodes(nodes);
+}
+
+fn flatten_nodes(nodes: Vec<CssNode>) -> Vec<CssNode> {
+    let mut output = Vec::with_capacity(nodes.len());
+
+    for node in nodes {
+        match node {
+            CssNode::Rule {
+                selectors,
+                children,
+            } => flatten_rule(&mut output, selectors, children),
+            CssNode::AtRule {
+                name,
+                prelude,
+                children: Some(children),
+            } => output.push(CssNode::AtRule {
+                name,
+                prelude,
+                children: Some(flatten_nodes(children)),
+            }),
+            node => output.push(node),
+        }
+    }
+
+    output
+}
+
+/// Writes a rule, followed by every rule nested inside of it.
+fn flatten_rule(output: &mut Vec<CssNode>, selectors: Vec<Selector>, children: Vec<CssNode>) {
+    let (nested, declarations): (Vec<_>, Vec<_>) =
+        children.into_iter().partition(|child| match child {
+            CssNode::Rule { .. } => true,
+            CssNode::AtRule { children, .. } => children.is_some(),
+            _ => false,
+        });
+
+    if !declarations.is_empty() || nested.is_empty() {
+        output.push(CssNode::Rule {
+            selectors: selectors.clone(),
+            children: declarations,
+        });
+    }
+
+    for child in nested {
+        match child {
+            CssNode::Rule {
+                selectors: child_selectors,
+                children,
+            } => flatten_rule(
+                output,
+                nest_selectors(&selectors, &child_selectors),
+                children,
+            ),
+            // `@media` inside of a rule applies to the rule, so the rule goes inside of it
+            CssNode::AtRule {
+                name,
+                prelude,
+                children: Some(children),
+            } => {
+                let mut inner = Vec::new();
+                flatten_rule(&mut inner, selectors.clone(), children);
+
+                output.push(CssNode::AtRule {
+                    name,
+                    prelude,
+                    children: Some(inner),
+                });
+            }
+            _ => (),
+        }
+    }
+}
+
+fn nest_selectors(parents: &[Selector], children: &[Selector]) -> Vec<Selector> {
+    let mut selectors = Vec::with_capacity(parents.len() * children.len());
+
+    for parent in parents {
+        for child in children {
+            selectors.push(nest_selector(parent, child));
+        }
+    }
+
+    selectors
+}
+
+/// Puts a selector inside of its parent. Wherever the child has a `&`, the parent is put there,
+/// otherwise the child is a descendant of the parent, like `.card .title`.
+fn nest_selector(parent: &Selector, child: &Selector) -> Selector {
+    if parent.compounds.is_empty() {
+        return child.clone();
+    }
+
+    let has_nesting = child
+        .compounds
+        .iter()
+        .any(|compound| compound.parts.contains(&SimpleSelector::Nesting));
+
+    if !has_nesting {
+        let mut compounds = parent.compounds.clone();
+        let mut rest = child.compounds.clone();
+
+        // `> .title` is relative to the parent already
+        if let Some(first) = rest.first_mut() {
+            first.combinator.get_or_insert(Combinator::Descendant);
+        }
+
+        compounds.extend(rest);
+        return nest_arguments(compounds, parent);
+    }
+
+    let mut compounds = Vec::with_capacity(parent.compounds.len() + child.compounds.len());
+
+    for compound in child.compounds.iter() {
+        let index = match compound
+            .parts
+            .iter()
+            .position(|part| *part == SimpleSelector::Nesting)
+        {
+            Some(index) => index,
+            None => {
+                compounds.push(compound.clone());
+                continue;
+            }
+        };
+
+        let mut replacement = parent.compounds.clone();
+        replacement[0].combinator = compound.combinator;
+
+        // `.featured&:hover` adds to the last part of the parent
+        let last = replacement.last_mut().unwrap();
+        let mut parts = compound.parts[..index].to_vec();
+        parts.append(&mut last.parts);
+        parts.extend(
+            compound.parts[index + 1..]
+                .iter()
+                .filter(|part| **part != SimpleSelector::Nesting)
+                .cloned(),
+        );
+
+        // an element's name always has to come first
+        if let Some(index) = parts
+            .iter()
+            .position(|part| matches!(part, SimpleSelector::Type(_) | SimpleSelector::Universal))
+        {
+            let name = parts.remove(index);
+            parts.insert(0, name);
+        }
+
+        last.parts = parts;
+        compounds.extend(replacement);
+    }
+
+    nest_arguments(compounds, parent)
+}
+
+/// Replaces a `&` inside of something like `:not(&)` with the parent.
+fn nest_arguments(mut compounds: Vec<Compound>, parent: &Selector) -> Selector {
+    for part in compounds
+        .iter_mut()
+        .flat_map(|compound| compound.parts.iter_mut())
+    {
+        if let SimpleSelector::PseudoClass(_, Some(arguments)) = part {
+            if arguments.contains('&') {
+                *arguments = arguments.replace('&', &format!(":is({})", parent));
+            }
+        }
+    }
+
+    Selector { compounds }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flattened(css: &str) -> String {
+        let mut stylesheet = Stylesheet::parse(css).unwrap();
+        flatten(&mut stylesheet);
+        stylesheet.write(true)
+    }
+
+    #[test]
+    fn nests_descendants() {
+        assert_eq!(
+            flattened(".card { color: red; .title { margin: 0 } > p { padding: 0 } }"),
+            ".card{color:red}.card .title{margin:0}.card>p{padding:0}"
+        );
+    }
+
+    #[test]
+    fn replaces_the_nesting_selector() {
+        assert_eq!(
+            flattened(".a, .b { &:hover { color: red } .dark & { color: blue } }"),
+            ".a:hover,.b:hover{color:red}.dark .a,.dark .b{color:blue}"
+        );
+        assert_eq!(
+            flattened(".button { a& { color: red } }"),
+            "a.button{color:red}"
+        );
+    }
+
+    #[test]
+    fn replaces_the_nesting_selector_in_arguments() {
+        assert_eq!(
+            flattened(".a .b { :not(&) { color: red } }"),
+            ".a .b :not(:is(.a .b)){color:red}"
+        );
+    }
+
+    #[test]
+    fn moves_media_outside() {
+        assert_eq!(
+            flattened(".card { color: red; @media (min-width: 600px) { color: blue; .title { margin: 0 } } }"),
+            ".card{color:red}@media (min-width: 600px){.card{color:blue}.card .title{margin:0}}"
+        );
+    }
+
+    #[test]
+    fn leaves_flat_css_alone() {
+        let css = ".a{color:red}@media print{.b{color:blue}}";
+        assert_eq!(flattened(css), css);
+    }
+}
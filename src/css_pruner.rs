@@ -0,0 +1,430 @@
+//! Removes CSS which can never match anything on a page, by matching every selector against the
+//! HTML that was built for the page.
+//!
+//! Only what's known at build time is checked: the elements, their classes, ids and other
+//! attributes, and how they're nested. Pseudo-classes like `:hover` could match at any time, so
+//! they're always assumed to match. HTML that's written as-is, like the output of `{{{ html }}}`
+//! and `<GoodWeb-Raw>`, is parsed so that what's in it is known too.
+
+use crate::css::{
+    unescape_identifier, Combinator, Compound, CssNode, Selector, SimpleSelector, Stylesheet,
+};
+use crate::html_parser;
+use crate::html_writer::HtmlNode;
+use crate::page_builder::unescape_xml;
+
+/// Removes every selector which doesn't match any element of the documents, and every rule that
+/// has no selectors left.
+///
+/// The safelist holds classes, ids and attributes which may be added later on, e.g. by
+/// JavaScript, written like `.is-open`, `#modal` or `[data-state]`. A trailing `*` matches
+/// anything starting with the rest, like `.js-*`.
+///
+/// If a document has HTML that can't be understood, nothing is removed, and `None` is returned.
+pub fn prune(
+    stylesheet: &mut Stylesheet,
+    documents: &[&[HtmlNode]],
+    safelist: &[String],
+) -> Option<()> {
+    let mut elements = Vec::new();
+    for document in documents {
+        index_elements(&mut elements, document, None, &mut None)?;
+    }
+
+    let page = Page { elements, safelist };
+    prune_nodes(&mut stylesheet.nodes, &page);
+    Some(())
+}
+
+struct Page<'a> {
+    elements: Vec<Element>,
+    safelist: &'a [String],
+}
+
+// An element, along with the elements around it
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    parent: Option<usize>,
+    previous_sibling: Option<usize>,
+}
+
+impl Element {
+    #[inline]
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn index_elements(
+    elements: &mut Vec<Element>,
+    nodes: &[HtmlNode],
+    parent: Option<usize>,
+    previous_sibling: &mut Option<usize>,
+) -> Option<()> {
+    for node in nodes {
+        let element = match node {
+            HtmlNode::Element(element) => element,
+            // the elements in it are siblings of the ones around it
+            HtmlNode::Html(html) => {
+                index_elements(
+                    elements,
+                    &html_parser::to_nodes(html)?,
+                    parent,
+                    previous_sibling,
+                )?;
+                continue;
+            }
+            _ => continue,
+        };
+
+        let index = elements.len();
+        elements.push(Element {
+            name: element.name.clone(),
+            attributes: element
+                .attributes
+                .iter()
+                .map(|(name, value)| (name.clone(), unescape_xml(value).into_owned()))
+                .collect(),
+            parent,
+            previous_sibling: *previous_sibling,
+        });
+
+        index_elements(elements, &element.children, Some(index), &mut None)?;
+        *previous_sibling = Some(index);
+    }
+
+    Some(())
+}
+
+fn prune_nodes(nodes: &mut Vec<CssNode>, page: &Page<'_>) {
+    nodes.retain_mut(|node| match node {
+        CssNode::Rule {
+            selectors,
+            children,
+        } => {
+            selectors.retain(|selector| page.matches_any(selector));
+
+            // nested rules are relative to this one, so they're only kept along with it
+            if !selectors.is_empty() {
+                prune_nodes(children, page);
+            }
+
+            !selectors.is_empty()
+        }
+        // @media, @supports and the like. there's no point in keeping them once they're empty
+        CssNode::AtRule {
+            name,
+            children: Some(children),
+            ..
+        } if name != "font-face" && !name.ends_with("keyframes") => {
+            let was_empty = children.is_empty();
+            prune_nodes(children, page);
+            was_empty || !children.is_empty()
+        }
+        _ => true,
+    });
+}
+
+impl Page<'_> {
+    fn matches_any(&self, selector: &Selector) -> bool {
+        let last = match selector.compounds.len().checked_sub(1) {
+            Some(last) => last,
+            None => return true,
+        };
+
+        (0..self.elements.len()).any(|element| self.matches(&selector.compounds, last, element))
+    }
+
+    /// Whether the compound at the index, and every compound before it, matches the element.
+    fn matches(&self, compounds: &[Compound], index: usize, element: usize) -> bool {
+        let compound = &compounds[index];
+
+        if !self.matches_compound(compound, element) {
+            return false;
+        }
+
+        // a relative selector, like `> .title`, is relative to something that's already matched
+        if index == 0 {
+            return true;
+        }
+
+        let current = &self.elements[element];
+        match compound.combinator {
+            Some(Combinator::Child) => current
+                .parent
+                .is_some_and(|parent| self.matches(compounds, index - 1, parent)),
+            Some(Combinator::NextSibling) => current
+                .previous_sibling
+                .is_some_and(|sibling| self.matches(compounds, index - 1, sibling)),
+            Some(Combinator::SubsequentSibling) => {
+                let mut sibling = current.previous_sibling;
+                while let Some(index_of_sibling) = sibling {
+                    if self.matches(compounds, index - 1, index_of_sibling) {
+                        return true;
+                    }
+
+                    sibling = self.elements[index_of_sibling].previous_sibling;
+                }
+
+                false
+            }
+            Some(Combinator::Descendant) | None => {
+                let mut ancestor = current.parent;
+                while let Some(index_of_ancestor) = ancestor {
+                    if self.matches(compounds, index - 1, index_of_ancestor) {
+                        return true;
+                    }
+
+                    ancestor = self.elements[index_of_ancestor].parent;
+                }
+
+                false
+            }
+        }
+    }
+
+    fn matches_compound(&self, compound: &Compound, element: usize) -> bool {
+        let element = &self.elements[element];
+
+        compound.parts.iter().all(|part| match part {
+            SimpleSelector::Type(name) => element.name.eq_ignore_ascii_case(name),
+            SimpleSelector::Class(class) => {
+                let class = unescape_identifier(class);
+                is_listed(self.safelist, '.', &class)
+                    || element
+                        .attribute("class")
+                        .is_some_and(|classes| classes.split_whitespace().any(|name| name == class))
+            }
+            SimpleSelector::Id(id) => {
+                let id = unescape_identifier(id);
+                is_listed(self.safelist, '#', &id) || element.attribute("id") == Some(&*id)
+            }
+            SimpleSelector::Attribute(inner) => self.matches_attribute(element, inner),
+            // states, structure and anything else that can't be known for sure
+            SimpleSelector::Universal
+            | SimpleSelector::Nesting
+            | SimpleSelector::PseudoClass(..)
+            | SimpleSelector::PseudoElement(..) => true,
+        })
+    }
+
+    /// Matches the inside of an attribute selector, e.g. `type="text"` or `href^='http' i`.
+    fn matches_attribute(&self, element: &Element, inner: &str) -> bool {
+        let operator_start = inner.find(['=', '~', '|', '^', '$', '*']);
+
+        let (name, rest) = match operator_start {
+            Some(start) => (inner[..start].trim(), &inner[start..]),
+            None => (inner.trim(), ""),
+        };
+
+        // a namespace, like `[xlink|href]`, isn't worth the trouble
+        if is_listed(self.safelist, '[', name) || (!rest.is_empty() && !rest.contains('=')) {
+            return true;
+        }
+
+        let actual = match element.attribute(name) {
+            Some(actual) => actual,
+            None => return false,
+        };
+
+        if rest.is_empty() {
+            return true;
+        }
+
+        let (operator, expected) = match rest.split_once('=') {
+            Some((operator, expected)) => (operator, expected.trim()),
+            None => return true,
+        };
+
+        // the value may be quoted, and may be followed by a flag like `i`
+        let (expected, case_insensitive) = match expected.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                let end = expected[1..]
+                    .find(quote)
+                    .map_or(expected.len(), |end| end + 1);
+                let flags = expected.get(end + 1..).unwrap_or("").trim();
+                (&expected[1..end], flags.eq_ignore_ascii_case("i"))
+            }
+            _ => match expected.split_once(char::is_whitespace) {
+                Some((value, flags)) => (value, flags.trim().eq_ignore_ascii_case("i")),
+                None => (expected, false),
+            },
+        };
+
+        let (actual, expected) = if case_insensitive {
+            (actual.to_lowercase(), expected.to_lowercase())
+        } else {
+            (actual.to_owned(), expected.to_owned())
+        };
+
+        match operator {
+            "" => actual == expected,
+            "~" => actual.split_whitespace().any(|word| word == expected),
+            "|" => actual == expected || actual.starts_with(&(expected + "-")),
+            "^" => !expected.is_empty() && actual.starts_with(&expected),
+            "$" => !expected.is_empty() && actual.ends_with(&expected),
+            "*" => !expected.is_empty() && actual.contains(&expected),
+            // something newer than this, so keep the rule to be safe
+            _ => true,
+        }
+    }
+}
+
+/// Whether a name is in a list like the safelist, where entries are written like `.is-open`,
+/// `#modal` or `[data-state]`, and a trailing `*` matches anything starting with the rest.
+pub fn is_listed(list: &[String], kind: char, name: &str) -> bool {
+    list.iter().any(|entry| {
+        let entry = match entry.strip_prefix(kind) {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        // attributes are written like `[data-state]`
+        let entry = if kind == '[' {
+            entry.strip_suffix(']').unwrap_or(entry)
+        } else {
+            entry
+        };
+
+        match entry.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == entry,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pruned(css: &str, document: &[HtmlNode], safelist: &[&str]) -> String {
+        let safelist = safelist
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        let mut stylesheet = Stylesheet::parse(css).unwrap();
+        prune(&mut stylesheet, &[document], &safelist).unwrap();
+        stylesheet.write(true)
+    }
+
+    fn card() -> Vec<HtmlNode> {
+        vec![HtmlNode::element(
+            "div",
+            &[("class", "card"), ("id", "main")],
+            vec![
+                HtmlNode::element("h2", &[("class", "title")], Vec::new()),
+                HtmlNode::element(
+                    "a",
+                    &[("href", "https://example.com"), ("lang", "en-GB")],
+                    Vec::new(),
+                ),
+            ],
+        )]
+    }
+
+    #[test]
+    fn removes_what_doesnt_match() {
+        assert_eq!(
+            pruned(
+                ".card .title, .panel .title { color: red } .missing { color: blue } #main { margin: 0 }",
+                &card(),
+                &[]
+            ),
+            ".card .title{color:red}#main{margin:0}"
+        );
+    }
+
+    #[test]
+    fn checks_how_elements_are_related() {
+        assert_eq!(
+            pruned(
+                ".card > .title { color: red } .title > .card { color: blue } h2 + a { margin: 0 } a ~ h2 { margin: 1px }",
+                &card(),
+                &[]
+            ),
+            ".card>.title{color:red}h2+a{margin:0}"
+        );
+    }
+
+    #[test]
+    fn matches_attributes() {
+        assert_eq!(
+            pruned(
+                "[href^=\"https\"] { color: red } [href$='.pdf'] { color: blue } [lang|=en] { margin: 0 } [href*=EXAMPLE i] { padding: 0 } [target] { border: 0 }",
+                &card(),
+                &[]
+            ),
+            "[href^=\"https\"]{color:red}[lang|=en]{margin:0}[href*=EXAMPLE i]{padding:0}"
+        );
+    }
+
+    #[test]
+    fn keeps_pseudo_classes() {
+        assert_eq!(
+            pruned(
+                ".card:hover { color: red } .title:not(.active) { color: blue } .missing:not(.card) { margin: 0 }",
+                &card(),
+                &[]
+            ),
+            ".card:hover{color:red}.title:not(.active){color:blue}"
+        );
+    }
+
+    #[test]
+    fn keeps_the_safelist() {
+        assert_eq!(
+            pruned(
+                ".is-open { color: red } .js-toggle { color: blue } #modal { margin: 0 } .other { padding: 0 }",
+                &card(),
+                &[".is-open", ".js-*", "#modal"]
+            ),
+            ".is-open{color:red}.js-toggle{color:blue}#modal{margin:0}"
+        );
+    }
+
+    #[test]
+    fn removes_empty_media() {
+        assert_eq!(
+            pruned(
+                "@media print { .missing { color: red } } @media screen { .card { color: blue } }",
+                &card(),
+                &[]
+            ),
+            "@media screen{.card{color:blue}}"
+        );
+    }
+
+    #[test]
+    fn looks_inside_of_html() {
+        let document = vec![HtmlNode::element(
+            "main",
+            &[("class", "content")],
+            vec![
+                HtmlNode::Html("text &amp; <b class=\"highlight\">bold</b>".to_owned()),
+                HtmlNode::Html("<span class='raw'><i id=icon></i></span>".to_owned()),
+            ],
+        )];
+
+        assert_eq!(
+            pruned(
+                ".content .highlight { color: red } .content > .raw #icon { color: blue } .highlight + .raw { margin: 0 } .missing { padding: 0 }",
+                &document,
+                &[]
+            ),
+            ".content .highlight{color:red}.content>.raw #icon{color:blue}.highlight+.raw{margin:0}"
+        );
+    }
+
+    #[test]
+    fn doesnt_prune_html_it_cant_read() {
+        let document = vec![HtmlNode::Html("<b class=\"never-closed>".to_owned())];
+        let mut stylesheet = Stylesheet::parse(".missing { color: red }").unwrap();
+
+        assert!(prune(&mut stylesheet, &[&document], &[]).is_none());
+        assert_eq!(stylesheet.write(true), ".missing{color:red}");
+    }
+}
@@ -0,0 +1,476 @@
+//! Shortens class names, and optionally ids, to names as short as possible, like `a` or `bX`.
+//!
+//! Names are renamed the same way on every page, so a name can be looked up in the mapping
+//! written to the build. Names used from outside of GoodWeb, e.g. by scripts, have to be
+//! excluded, or they'll no longer match.
+//!
+//! HTML that's written as-is, like the output of `{{{ html }}}` and `<GoodWeb-Raw>`, is renamed
+//! too. Only its attributes are changed, the rest of it is left exactly as it was written.
+
+use crate::config::CssConfig;
+use crate::css::{
+    parse_selectors, unescape_identifier, write_selectors, CssNode, Selector, SimpleSelector,
+    Stylesheet,
+};
+use crate::css_assets;
+use crate::css_pruner::is_listed;
+use crate::html_parser;
+use crate::html_writer::HtmlNode;
+use crate::page_builder::unescape_xml;
+use crate::templating::escape_html_into;
+use std::collections::BTreeMap;
+
+/// Where the names are written to after the build, to be able to tell what `a` used to be.
+pub const RENAME_FILE: &str = "build/renames.json";
+
+/// Attributes which hold one or more ids, separated by whitespace.
+const ID_REFERENCES: &[&str] = &[
+    "for",
+    "form",
+    "list",
+    "headers",
+    "popovertarget",
+    "aria-activedescendant",
+    "aria-controls",
+    "aria-describedby",
+    "aria-details",
+    "aria-errormessage",
+    "aria-flowto",
+    "aria-labelledby",
+    "aria-owns",
+];
+
+// Names can't start with a digit or a `-`, but can contain them
+const FIRST_CHARACTERS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const CHARACTERS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789-_";
+
+pub struct Renamer<'c> {
+    config: &'c CssConfig,
+    classes: Names,
+    ids: Names,
+}
+
+#[derive(Default)]
+struct Names {
+    renamed: BTreeMap<String, String>,
+    next: usize,
+}
+
+impl Renamer<'_> {
+    pub fn new(config: &CssConfig) -> Renamer<'_> {
+        Renamer {
+            config,
+            classes: Names::default(),
+            ids: Names::default(),
+        }
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.config.rename.classes || self.config.rename.ids
+    }
+
+    /// Renames the classes and ids of every element, and everything that refers to an id.
+    pub fn rename_document(&mut self, nodes: &mut [HtmlNode]) {
+        for node in nodes.iter_mut() {
+            match node {
+                HtmlNode::Element(element) => {
+                    for (name, value) in element.attributes.iter_mut() {
+                        if let Some(renamed) = self.rename_attribute(name, value) {
+                            *value = renamed;
+                        }
+                    }
+
+                    self.rename_document(&mut element.children);
+                }
+                HtmlNode::Html(html) if html.contains('<') => {
+                    *html = html_parser::rewrite_attributes(html, &mut |name, value| {
+                        self.rename_attribute(name, value)
+                    });
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Renames the classes and ids in every selector of the stylesheet.
+    pub fn rename_stylesheet(&mut self, stylesheet: &mut Stylesheet) {
+        self.rename_nodes(&mut stylesheet.nodes);
+    }
+
+    /// Writes every name that was renamed, and what it was renamed to, to the build.
+    pub fn write(&self) -> Option<()> {
+        let names = serde_json::json!({
+            "classes": self.classes.renamed,
+            "ids": self.ids.renamed,
+        });
+
+        let mut data = serde_json::to_string_pretty(&names).ok()?;
+        data.push('\n');
+
+        match std::fs::write(RENAME_FILE, data) {
+            Ok(_) => Some(()),
+            Err(_) => {
+                println!("[ERR] couldn't write renamed names to '{}'", RENAME_FILE);
+                None
+            }
+        }
+    }
+
+    fn rename_nodes(&mut self, nodes: &mut [CssNode]) {
+        for node in nodes.iter_mut() {
+            match node {
+                // `mask: url(#mask)` and the like
+                CssNode::Declaration { value, .. } => {
+                    if let Some(renamed) = self.rename_urls(value) {
+                        *value = renamed;
+                    }
+                }
+                CssNode::Keyframe { children, .. } => self.rename_nodes(children),
+                CssNode::Rule {
+                    selectors,
+                    children,
+                } => {
+                    for selector in selectors.iter_mut() {
+                        self.rename_selector(selector);
+                    }
+
+                    self.rename_nodes(children);
+                }
+                CssNode::AtRule {
+                    children: Some(children),
+                    ..
+                } => self.rename_nodes(children),
+                _ => (),
+            }
+        }
+    }
+
+    fn rename_selector(&mut self, selector: &mut Selector) {
+        for compound in selector.compounds.iter_mut() {
+            for part in compound.parts.iter_mut() {
+                match part {
+                    SimpleSelector::Class(name) => {
+                        if let Some(renamed) = self.rename('.', &unescape_identifier(name)) {
+                            *name = renamed;
+                        }
+                    }
+                    SimpleSelector::Id(name) => {
+                        if let Some(renamed) = self.rename('#', &unescape_identifier(name)) {
+                            *name = renamed;
+                        }
+                    }
+                    // the arguments of `:not(.a, .b)` and the like are selectors too
+                    SimpleSelector::PseudoClass(name, Some(arguments))
+                        if matches!(name.as_str(), "not" | "is" | "where" | "has") =>
+                    {
+                        let mut selectors = match parse_selectors(arguments) {
+                            Some(selectors) => selectors,
+                            None => continue,
+                        };
+
+                        for selector in selectors.iter_mut() {
+                            self.rename_selector(selector);
+                        }
+
+                        arguments.clear();
+                        write_selectors(arguments, &selectors, false);
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    /// The new value of an attribute, if it has classes or ids in it. The value is escaped, and
+    /// so is the result.
+    fn rename_attribute(&mut self, name: &str, value: &str) -> Option<String> {
+        let kind = match name {
+            "class" => '.',
+            "id" => '#',
+            name if ID_REFERENCES.contains(&name) => '#',
+            // links to somewhere on this site, like `#main` or `/about#main`. ids are renamed
+            // the same way on every page, so the fragment still points at the right element
+            "href" | "xlink:href" if value.contains('#') => {
+                let (url, fragment) = value.split_at(value.find('#')?);
+
+                if !is_same_site(url) {
+                    return None;
+                }
+
+                return self
+                    .rename('#', &unescape_xml(&fragment[1..]))
+                    .map(|renamed| format!("{}#{}", url, renamed));
+            }
+            // SVG refers to gradients, masks and the like with `fill="url(#gradient)"`
+            _ if value.contains("url(") => {
+                let renamed = self.rename_urls(&unescape_xml(value))?;
+                let mut escaped = String::with_capacity(renamed.len());
+                escape_html_into(&mut escaped, &renamed);
+                return Some(escaped);
+            }
+            _ => return None,
+        };
+
+        Some(self.rename_list(kind, value))
+    }
+
+    /// Renames the ids in the `url(#id)`s of a value, if there are any.
+    fn rename_urls(&mut self, value: &str) -> Option<String> {
+        if !value.contains("url(") {
+            return None;
+        }
+
+        css_assets::rewrite_value(value, &mut |url| {
+            let id = url.strip_prefix('#')?;
+            self.rename('#', id).map(|renamed| format!("#{}", renamed))
+        })
+    }
+
+    /// Renames every name in a whitespace separated list, like a `class` attribute. The list is
+    /// escaped, and so is the result.
+    fn rename_list(&mut self, kind: char, list: &str) -> String {
+        list.split_whitespace()
+            .map(|name| match self.rename(kind, &unescape_xml(name)) {
+                Some(renamed) => renamed,
+                None => name.to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The new name of a class (`.`) or id (`#`), unless it keeps its name.
+    fn rename(&mut self, kind: char, name: &str) -> Option<String> {
+        let enabled = match kind {
+            '.' => self.config.rename.classes,
+            _ => self.config.rename.ids,
+        };
+
+        if !enabled || name.is_empty() || keeps(self.config, kind, name) {
+            return None;
+        }
+
+        let config = self.config;
+        let names = match kind {
+            '.' => &mut self.classes,
+            _ => &mut self.ids,
+        };
+
+        if let Some(renamed) = names.renamed.get(name) {
+            return Some(renamed.clone());
+        }
+
+        // a name that's kept can't be handed out to something else
+        let renamed = loop {
+            let candidate = short_name(names.next);
+            names.next += 1;
+
+            if !keeps(config, kind, &candidate) {
+                break candidate;
+            }
+        };
+
+        names.renamed.insert(name.to_owned(), renamed.clone());
+        Some(renamed)
+    }
+}
+
+/// Excluded names keep their name, and so does everything on the safelist, since it's used by
+/// something outside of GoodWeb.
+#[inline]
+fn keeps(config: &CssConfig, kind: char, name: &str) -> bool {
+    is_listed(&config.rename.exclude, kind, name) || is_listed(&config.safelist, kind, name)
+}
+
+/// Whether a link is to a page of this site, rather than to some other site.
+fn is_same_site(url: &str) -> bool {
+    url.is_empty()
+        || css_assets::is_relative_url(url)
+        || (url.starts_with('/') && !url.starts_with("//"))
+}
+
+/// The name for an index: `a` to `Z` first, then `aa`, `ba` and so on.
+fn short_name(index: usize) -> String {
+    let mut name = String::new();
+    name.push(FIRST_CHARACTERS[index % FIRST_CHARACTERS.len()] as char);
+
+    let mut rest = index / FIRST_CHARACTERS.len();
+    while rest > 0 {
+        rest -= 1;
+        name.push(CHARACTERS[rest % CHARACTERS.len()] as char);
+        rest /= CHARACTERS.len();
+    }
+
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RenameConfig;
+
+    fn config(classes: bool, ids: bool, exclude: &[&str]) -> CssConfig {
+        CssConfig {
+            rename: RenameConfig {
+                classes,
+                ids,
+                exclude: exclude.iter().map(|name| name.to_string()).collect(),
+            },
+            ..CssConfig::default()
+        }
+    }
+
+    fn renamed_css(renamer: &mut Renamer<'_>, css: &str) -> String {
+        let mut stylesheet = Stylesheet::parse(css).unwrap();
+        renamer.rename_stylesheet(&mut stylesheet);
+        stylesheet.write(true)
+    }
+
+    #[test]
+    fn short_names_count_up() {
+        assert_eq!(short_name(0), "a");
+        assert_eq!(short_name(51), "Z");
+        assert_eq!(short_name(52), "aa");
+        assert_eq!(short_name(53), "ba");
+        assert_eq!(short_name(52 + 52 * 64), "aaa");
+    }
+
+    #[test]
+    fn renames_html_and_css_the_same_way() {
+        let config = config(true, true, &[]);
+        let mut renamer = Renamer::new(&config);
+
+        let mut document = vec![HtmlNode::element(
+            "div",
+            &[("class", "card  dark"), ("id", "main")],
+            vec![
+                HtmlNode::element("label", &[("for", "main")], Vec::new()),
+                HtmlNode::element("a", &[("href", "#main")], Vec::new()),
+                HtmlNode::element("a", &[("href", "/about#main")], Vec::new()),
+                HtmlNode::element("a", &[("href", "about.html#main")], Vec::new()),
+                HtmlNode::element("a", &[("href", "https://example.com/#main")], Vec::new()),
+                HtmlNode::element("a", &[("href", "//example.com/#main")], Vec::new()),
+            ],
+        )];
+        renamer.rename_document(&mut document);
+
+        assert_eq!(
+            document,
+            vec![HtmlNode::element(
+                "div",
+                &[("class", "a b"), ("id", "a")],
+                vec![
+                    HtmlNode::element("label", &[("for", "a")], Vec::new()),
+                    HtmlNode::element("a", &[("href", "#a")], Vec::new()),
+                    HtmlNode::element("a", &[("href", "/about#a")], Vec::new()),
+                    HtmlNode::element("a", &[("href", "about.html#a")], Vec::new()),
+                    HtmlNode::element("a", &[("href", "https://example.com/#main")], Vec::new()),
+                    HtmlNode::element("a", &[("href", "//example.com/#main")], Vec::new()),
+                ],
+            )]
+        );
+
+        assert_eq!(
+            renamed_css(
+                &mut renamer,
+                ".card.dark, #main:not(.card), .new { color: red }"
+            ),
+            ".a.b,#a:not(.a),.c{color:red}"
+        );
+
+        assert_eq!(renamer.classes.renamed.get("new").unwrap(), "c");
+        assert_eq!(renamer.ids.renamed.len(), 1);
+    }
+
+    #[test]
+    fn keeps_excluded_names() {
+        let mut config = config(true, false, &[".js-*"]);
+        config.safelist.push(".is-open".to_owned());
+        let mut renamer = Renamer::new(&config);
+
+        assert_eq!(
+            renamed_css(
+                &mut renamer,
+                ".js-toggle, .is-open, .card, #main { color: red }"
+            ),
+            ".js-toggle,.is-open,.a,#main{color:red}"
+        );
+    }
+
+    #[test]
+    fn renames_svg_references() {
+        let config = config(false, true, &[]);
+        let mut renamer = Renamer::new(&config);
+
+        let mut document = vec![HtmlNode::element(
+            "svg",
+            &[],
+            vec![
+                HtmlNode::element("linearGradient", &[("id", "fade")], Vec::new()),
+                HtmlNode::element(
+                    "rect",
+                    &[
+                        ("fill", "url(#fade)"),
+                        ("style", "mask: url(&quot;#fade&quot;)"),
+                    ],
+                    Vec::new(),
+                ),
+                HtmlNode::element("use", &[("xlink:href", "#fade")], Vec::new()),
+            ],
+        )];
+        renamer.rename_document(&mut document);
+
+        assert_eq!(
+            document,
+            vec![HtmlNode::element(
+                "svg",
+                &[],
+                vec![
+                    HtmlNode::element("linearGradient", &[("id", "a")], Vec::new()),
+                    HtmlNode::element(
+                        "rect",
+                        &[("fill", "url(#a)"), ("style", "mask: url(#a)")],
+                        Vec::new()
+                    ),
+                    HtmlNode::element("use", &[("xlink:href", "#a")], Vec::new()),
+                ],
+            )]
+        );
+
+        assert_eq!(
+            renamed_css(
+                &mut renamer,
+                ".card { filter: url(#fade); background: url(img.png) }"
+            ),
+            ".card{filter:url(#a);background:url(img.png)}"
+        );
+    }
+
+    #[test]
+    fn renames_html_written_as_is() {
+        let config = config(true, true, &[]);
+        let mut renamer = Renamer::new(&config);
+
+        let mut document = vec![
+            HtmlNode::Html("<b class=\"highlight\">x &amp; y</b>".to_owned()),
+            HtmlNode::Html(
+                "<!-- class=\"comment\" --><span class='raw' data-x=1 id=icon>r</span>\
+                 <script>a.className = \"highlight\"</script>"
+                    .to_owned(),
+            ),
+        ];
+        renamer.rename_document(&mut document);
+
+        assert_eq!(
+            document,
+            vec![
+                HtmlNode::Html("<b class=\"a\">x &amp; y</b>".to_owned()),
+                HtmlNode::Html(
+                    "<!-- class=\"comment\" --><span class='b' data-x=1 id=\"a\">r</span>\
+                     <script>a.className = \"highlight\"</script>"
+                        .to_owned()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,801 @@
+//! Turns real-world HTML into well-formed XML, so that it can be parsed like any other component.
+//!
+//! This isn't a full HTML5 parser, but it handles what's commonly written by hand:
+//! - void elements without an end tag, e.g. `<br>` or `<input>`
+//! - attributes without a value (`<input disabled>`) or without quotes (`<a href=/about>`)
+//! - named character references such as `&nbsp;`, and a bare `&` or `<` in text
+//! - end tags which are implied, e.g. `<li>` or `<p>` that are never closed
+//! - `<script>` and `<style>`, whose contents aren't markup
+//!
+//! Mistakes which can't be guessed at, like a quote or comment that's never closed, are errors.
+
+use crate::html_writer::{is_void_element, HtmlElement, HtmlNode};
+use crate::templating::escape_html_into;
+use std::fmt::{self, Display};
+
+#[derive(Debug)]
+pub struct ConvertError {
+    pub line: usize,
+    pub message: &'static str,
+}
+
+impl Display for ConvertError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "line {}: {}", self.line, self.message)
+    }
+}
+
+pub fn to_xml(html: &str) -> Result<String, ConvertError> {
+    let mut converter = Converter {
+        input: html,
+        position: 0,
+        output: String::with_capacity(html.len() + html.len() / 8),
+        open_elements: Vec::new(),
+    };
+
+    converter.convert()?;
+    Ok(converter.output)
+}
+
+/// Parses HTML into elements, so that HTML which is written as-is (`HtmlNode::Html`) can be
+/// looked at like any other element. Text and comments are left out. `None` if it isn't HTML
+/// that GoodWeb understands.
+pub fn to_nodes(html: &str) -> Option<Vec<HtmlNode>> {
+    // most of it is text
+    if !html.contains('<') {
+        return Some(Vec::new());
+    }
+
+    let xml = to_xml(html).ok()?;
+
+    // inline SVG may use `xlink:href` without declaring it
+    let xml = format!(
+        "<root xmlns:xlink=\"http://www.w3.org/1999/xlink\">{}</root>",
+        xml
+    );
+    let document = roxmltree::Document::parse(&xml).ok()?;
+
+    Some(element_nodes(document.root_element()))
+}
+
+fn element_nodes(node: roxmltree::Node<'_, '_>) -> Vec<HtmlNode> {
+    node.children()
+        .filter(|child| child.is_element())
+        .map(|child| {
+            let attributes = child
+                .attributes()
+                .iter()
+                .map(|attribute| {
+                    let mut value = String::with_capacity(attribute.value().len());
+                    escape_html_into(&mut value, attribute.value());
+                    (attribute.name().to_owned(), value)
+                })
+                .collect();
+
+            HtmlNode::Element(HtmlElement {
+                name: child.tag_name().name().to_owned(),
+                attributes,
+                children: element_nodes(child),
+            })
+        })
+        .collect()
+}
+
+/// Rewrites the values of attributes in HTML that's written as-is, leaving everything else
+/// exactly as it is. `rewrite` is given the name of each attribute and its value, still escaped,
+/// and returns the new value if it's changed.
+pub fn rewrite_attributes(
+    html: &str,
+    rewrite: &mut dyn FnMut(&str, &str) -> Option<String>,
+) -> String {
+    let mut converter = Converter {
+        input: html,
+        position: 0,
+        output: String::with_capacity(html.len()),
+        open_elements: Vec::new(),
+    };
+
+    // everything before this has been written to the output
+    let mut copied = 0;
+
+    while let Some(character) = converter.peek() {
+        let rest = converter.rest();
+
+        if rest.starts_with("<!--") {
+            converter.skip_until("-->");
+        } else if rest.starts_with("<![CDATA[") {
+            converter.skip_until("]]>");
+        } else if rest.starts_with("<!") || rest.starts_with("<?") || rest.starts_with("</") {
+            converter.skip_until(">");
+        } else if character == '<' && rest[1..].starts_with(is_name_start) {
+            converter.position += 1;
+            let name = converter.take_while(is_name_char);
+
+            while let Some((attribute, start, end)) = converter.raw_attribute() {
+                let (quote, value) = match html[start..end].chars().next() {
+                    Some(quote @ ('"' | '\'')) => (quote, &html[start + 1..end - 1]),
+                    _ => ('"', &html[start..end]),
+                };
+
+                if let Some(value) = rewrite(attribute, value) {
+                    converter.output.push_str(&html[copied..start]);
+                    converter.output.push(quote);
+                    converter.output.push_str(&value);
+                    converter.output.push(quote);
+                    copied = end;
+                }
+            }
+
+            if matches!(
+                name.to_ascii_lowercase().as_str(),
+                "script" | "style" | "textarea" | "title"
+            ) {
+                converter.raw_text(name);
+            }
+        } else {
+            converter.position += character.len_utf8();
+        }
+    }
+
+    converter.output.push_str(&html[copied..]);
+    converter.output
+}
+
+struct Converter<'a> {
+    input: &'a str,
+    position: usize,
+    output: String,
+    open_elements: Vec<String>,
+}
+
+impl<'a> Converter<'a> {
+    fn convert(&mut self) -> Result<(), ConvertError> {
+        while let Some(character) = self.peek() {
+            let rest = self.rest();
+
+            if rest.starts_with("<!--") {
+                self.copy_until("-->", "comment is never closed")?;
+            } else if rest.starts_with("<![CDATA[") {
+                self.copy_until("]]>", "CDATA section is never closed")?;
+            } else if rest.starts_with("<!") {
+                // a doctype, which can't be in the middle of a document
+                self.skip_until(">");
+            } else if rest.starts_with("<?") {
+                self.copy_until("?>", "processing instruction is never closed")?;
+            } else if rest.starts_with("</") {
+                self.end_tag();
+            } else if character == '<' && rest[1..].starts_with(is_name_start) {
+                self.start_tag()?;
+            } else if character == '<' {
+                self.output.push_str("&lt;");
+                self.position += 1;
+            } else if character == '&' {
+                self.character_reference();
+            } else {
+                self.output.push(character);
+                self.position += character.len_utf8();
+            }
+        }
+
+        while let Some(name) = self.open_elements.pop() {
+            push_end_tag(&mut self.output, &name);
+        }
+
+        Ok(())
+    }
+
+    fn start_tag(&mut self) -> Result<(), ConvertError> {
+        // skip the '<'
+        self.position += 1;
+        let name = self.take_while(is_name_char).to_owned();
+        let mut attributes: Vec<(String, String)> = Vec::new();
+        let mut self_closing = false;
+
+        loop {
+            self.take_while(char::is_whitespace);
+
+            match self.peek() {
+                None => break,
+                Some('>') => {
+                    self.position += 1;
+                    break;
+                }
+                Some('/') => {
+                    self.position += 1;
+                    if self.peek() == Some('>') {
+                        self.position += 1;
+                        self_closing = true;
+                        break;
+                    }
+                }
+                Some(_) => {
+                    let attribute = self.attribute()?;
+
+                    // XML doesn't allow duplicate attributes, HTML uses the first one
+                    if !attribute.0.is_empty()
+                        && !attributes.iter().any(|(name, _)| name == &attribute.0)
+                    {
+                        attributes.push(attribute);
+                    }
+                }
+            }
+        }
+
+        self.close_implied(&name);
+
+        self.output.push('<');
+        self.output.push_str(&name);
+        for (attribute, value) in attributes.iter() {
+            self.output.push(' ');
+            self.output.push_str(attribute);
+            self.output.push_str("=\"");
+            self.output.push_str(value);
+            self.output.push('"');
+        }
+
+        if self_closing || is_void_element(&name.to_ascii_lowercase()) || is_goodweb_void(&name) {
+            self.output.push_str("/>");
+            return Ok(());
+        }
+
+        self.output.push('>');
+
+        match name.to_ascii_lowercase().as_str() {
+            "script" | "style" => {
+                let text = self.raw_text(&name);
+                // escaped rather than put in CDATA, which GoodWeb would leave untemplated
+                escape_html_into(&mut self.output, text);
+                push_end_tag(&mut self.output, &name);
+            }
+            "textarea" | "title" => {
+                let text = self.raw_text(&name);
+                push_escaped_text(&mut self.output, text);
+                push_end_tag(&mut self.output, &name);
+            }
+            _ => self.open_elements.push(name),
+        }
+
+        Ok(())
+    }
+
+    /// Reads a single attribute, returning its name and its value escaped for XML.
+    fn attribute(&mut self) -> Result<(String, String), ConvertError> {
+        let name = self
+            .take_while(|character| {
+                !character.is_whitespace() && !matches!(character, '=' | '>' | '/' | '"' | '\'')
+            })
+            .to_owned();
+
+        // a character that can't start a name - skip it so we don't loop forever
+        if name.is_empty() {
+            self.position += self.peek().map_or(0, char::len_utf8);
+            return Ok((name, String::new()));
+        }
+
+        self.take_while(char::is_whitespace);
+
+        if self.peek() != Some('=') {
+            return Ok((name, String::new()));
+        }
+
+        self.position += 1;
+        self.take_while(char::is_whitespace);
+
+        let raw_value = match self.peek() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                let start = self.position;
+                self.position += 1;
+                let value = self.take_while(|character| character != quote);
+
+                if self.peek() != Some(quote) {
+                    return Err(self.error(start, "attribute value's quote is never closed"));
+                }
+
+                self.position += 1;
+                value
+            }
+            _ => self.take_while(|character| !character.is_whitespace() && character != '>'),
+        };
+
+        let mut value = String::with_capacity(raw_value.len());
+        push_escaped_text(&mut value, raw_value);
+        Ok((name, value.replace('"', "&quot;")))
+    }
+
+    /// Reads the next attribute of a start tag that's already been read up to its attributes,
+    /// returning its name and where its value is, quotes included. `None` at the end of the tag.
+    fn raw_attribute(&mut self) -> Option<(&'a str, usize, usize)> {
+        loop {
+            self.take_while(char::is_whitespace);
+
+            match self.peek()? {
+                '>' => {
+                    self.position += 1;
+                    return None;
+                }
+                '/' | '=' | '"' | '\'' => self.position += 1,
+                _ => {
+                    let name = self.take_while(|character| {
+                        !character.is_whitespace()
+                            && !matches!(character, '=' | '>' | '/' | '"' | '\'')
+                    });
+
+                    self.take_while(char::is_whitespace);
+                    if self.peek() != Some('=') {
+                        continue;
+                    }
+
+                    self.position += 1;
+                    self.take_while(char::is_whitespace);
+
+                    let start = self.position;
+                    match self.peek() {
+                        Some(quote @ ('"' | '\'')) => {
+                            self.position += 1;
+                            self.take_while(|character| character != quote);
+
+                            // a quote that's never closed is left alone
+                            if self.peek() != Some(quote) {
+                                return None;
+                            }
+
+                            self.position += 1;
+                        }
+                        _ => {
+                            self.take_while(|character| {
+                                !character.is_whitespace() && character != '>'
+                            });
+                        }
+                    }
+
+                    return Some((name, start, self.position));
+                }
+            }
+        }
+    }
+
+    fn end_tag(&mut self) {
+        // skip the '</'
+        self.position += 2;
+        let name = self.take_while(is_name_char).to_owned();
+        self.skip_until(">");
+
+        let position = self
+            .open_elements
+            .iter()
+            .rposition(|open| open == &name)
+            .or_else(|| {
+                self.open_elements
+                    .iter()
+                    .rposition(|open| open.eq_ignore_ascii_case(&name))
+            });
+
+        // an end tag for something that isn't open (like `</br>`) is ignored
+        if let Some(position) = position {
+            while self.open_elements.len() > position {
+                let open = self.open_elements.pop().unwrap();
+                push_end_tag(&mut self.output, &open);
+            }
+        }
+    }
+
+    /// Closes the elements which end when an element with the given name starts, e.g. a `<li>`
+    /// ends the `<li>` before it.
+    fn close_implied(&mut self, name: &str) {
+        let name = name.to_ascii_lowercase();
+
+        while let Some(open) = self.open_elements.last() {
+            if !is_implicitly_closed_by(&open.to_ascii_lowercase(), &name) {
+                break;
+            }
+
+            let open = self.open_elements.pop().unwrap();
+            push_end_tag(&mut self.output, &open);
+        }
+    }
+
+    /// Reads everything up until the end tag of the element, and skips the end tag.
+    fn raw_text(&mut self, name: &str) -> &'a str {
+        let rest = self.rest();
+        let mut end_tag = String::with_capacity(name.len() + 2);
+        end_tag.push_str("</");
+        end_tag.push_str(&name.to_ascii_lowercase());
+
+        let end = rest
+            .to_ascii_lowercase()
+            .find(&end_tag)
+            .unwrap_or(rest.len());
+        self.position += end;
+        self.skip_until(">");
+
+        &rest[..end]
+    }
+
+    fn character_reference(&mut self) {
+        let rest = self.rest();
+        let reference = rest[1..]
+            .find(';')
+            .map(|end| &rest[1..end + 1])
+            .filter(|reference| {
+                !reference.is_empty()
+                    && reference
+                        .chars()
+                        .all(|character| character.is_ascii_alphanumeric() || character == '#')
+            });
+
+        let reference = match reference {
+            Some(reference) => reference,
+            None => {
+                self.output.push_str("&amp;");
+                self.position += 1;
+                return;
+            }
+        };
+
+        let is_xml = reference.starts_with('#')
+            || matches!(reference, "amp" | "lt" | "gt" | "quot" | "apos");
+
+        if is_xml {
+            self.output.push('&');
+            self.output.push_str(reference);
+            self.output.push(';');
+        } else {
+            match named_character(reference) {
+                Some(code) => {
+                    self.output.push_str("&#");
+                    self.output.push_str(&code.to_string());
+                    self.output.push(';');
+                }
+                None => {
+                    self.output.push_str("&amp;");
+                    self.output.push_str(reference);
+                    self.output.push(';');
+                }
+            }
+        }
+
+        self.position += reference.len() + 2;
+    }
+
+    #[inline]
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, predicate: F) -> &'a str {
+        let rest = self.rest();
+        let end = rest
+            .find(|character| !predicate(character))
+            .unwrap_or(rest.len());
+
+        self.position += end;
+        &rest[..end]
+    }
+
+    /// Copies everything up to and including the terminator. If it's missing, it's an error
+    /// rather than a guess, since everything after it would be lost.
+    fn copy_until(&mut self, terminator: &str, message: &'static str) -> Result<(), ConvertError> {
+        let rest = self.rest();
+
+        match rest.find(terminator) {
+            Some(end) => {
+                self.output.push_str(&rest[..end + terminator.len()]);
+                self.position += end + terminator.len();
+                Ok(())
+            }
+            None => Err(self.error(self.position, message)),
+        }
+    }
+
+    fn error(&self, position: usize, message: &'static str) -> ConvertError {
+        ConvertError {
+            line: self.input[..position].matches('\n').count() + 1,
+            message,
+        }
+    }
+
+    fn skip_until(&mut self, terminator: &str) {
+        let rest = self.rest();
+
+        self.position += match rest.find(terminator) {
+            Some(end) => end + terminator.len(),
+            None => rest.len(),
+        };
+    }
+}
+
+#[inline]
+fn is_name_start(character: char) -> bool {
+    character.is_ascii_alphabetic()
+}
+
+#[inline]
+fn is_name_char(character: char) -> bool {
+    character.is_ascii_alphanumeric() || matches!(character, '-' | '_' | '.' | ':')
+}
+
+/// GoodWeb's own elements that never have children, which HTML would otherwise leave open until
+/// their parent ends.
+#[inline]
+fn is_goodweb_void(name: &str) -> bool {
+    matches!(
+        name,
+        "GoodWeb-Inner" | "GoodWeb-Styles" | "GoodWeb-HeadOutlet"
+    )
+}
+
+fn push_end_tag(output: &mut String, name: &str) {
+    output.push_str("</");
+    output.push_str(name);
+    output.push('>');
+}
+
+/// Escapes text which may contain a bare `<` or `&`, keeping any character references.
+fn push_escaped_text(output: &mut String, text: &str) {
+    let mut converter = Converter {
+        input: text,
+        position: 0,
+        output: String::new(),
+        open_elements: Vec::new(),
+    };
+
+    while let Some(character) = converter.peek() {
+        match character {
+            '&' => converter.character_reference(),
+            '<' => {
+                converter.output.push_str("&lt;");
+                converter.position += 1;
+            }
+            _ => {
+                converter.output.push(character);
+                converter.position += character.len_utf8();
+            }
+        }
+    }
+
+    output.push_str(&converter.output);
+}
+
+fn is_implicitly_closed_by(open: &str, starting: &str) -> bool {
+    match open {
+        "li" => starting == "li",
+        "dt" | "dd" => matches!(starting, "dt" | "dd"),
+        "option" => matches!(starting, "option" | "optgroup"),
+        "td" | "th" => matches!(starting, "td" | "th" | "tr"),
+        "tr" => starting == "tr",
+        "p" => matches!(
+            starting,
+            "address"
+                | "article"
+                | "aside"
+                | "blockquote"
+                | "details"
+                | "div"
+                | "dl"
+                | "fieldset"
+                | "figure"
+                | "footer"
+                | "form"
+                | "h1"
+                | "h2"
+                | "h3"
+                | "h4"
+                | "h5"
+                | "h6"
+                | "header"
+                | "hr"
+                | "main"
+                | "nav"
+                | "ol"
+                | "p"
+                | "pre"
+                | "section"
+                | "table"
+                | "ul"
+        ),
+        _ => false,
+    }
+}
+
+/// The code points of the named character references that are commonly used.
+fn named_character(name: &str) -> Option<u32> {
+    let code = match name {
+        "nbsp" => 160,
+        "iexcl" => 161,
+        "cent" => 162,
+        "pound" => 163,
+        "curren" => 164,
+        "yen" => 165,
+        "brvbar" => 166,
+        "sect" => 167,
+        "uml" => 168,
+        "copy" => 169,
+        "ordf" => 170,
+        "laquo" => 171,
+        "not" => 172,
+        "shy" => 173,
+        "reg" => 174,
+        "macr" => 175,
+        "deg" => 176,
+        "plusmn" => 177,
+        "sup2" => 178,
+        "sup3" => 179,
+        "acute" => 180,
+        "micro" => 181,
+        "para" => 182,
+        "middot" => 183,
+        "cedil" => 184,
+        "sup1" => 185,
+        "ordm" => 186,
+        "raquo" => 187,
+        "frac14" => 188,
+        "frac12" => 189,
+        "frac34" => 190,
+        "iquest" => 191,
+        "times" => 215,
+        "divide" => 247,
+        "ensp" => 8194,
+        "emsp" => 8195,
+        "thinsp" => 8201,
+        "zwnj" => 8204,
+        "zwj" => 8205,
+        "ndash" => 8211,
+        "mdash" => 8212,
+        "lsquo" => 8216,
+        "rsquo" => 8217,
+        "sbquo" => 8218,
+        "ldquo" => 8220,
+        "rdquo" => 8221,
+        "bdquo" => 8222,
+        "dagger" => 8224,
+        "Dagger" => 8225,
+        "bull" => 8226,
+        "hellip" => 8230,
+        "prime" => 8242,
+        "Prime" => 8243,
+        "lsaquo" => 8249,
+        "rsaquo" => 8250,
+        "euro" => 8364,
+        "trade" => 8482,
+        "larr" => 8592,
+        "uarr" => 8593,
+        "rarr" => 8594,
+        "darr" => 8595,
+        "harr" => 8596,
+        "minus" => 8722,
+        "infin" => 8734,
+        "ne" => 8800,
+        "le" => 8804,
+        "ge" => 8805,
+        "spades" => 9824,
+        "clubs" => 9827,
+        "hearts" => 9829,
+        "diams" => 9830,
+        _ => return None,
+    };
+
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(html: &str) -> String {
+        to_xml(html).unwrap()
+    }
+
+    #[test]
+    fn closes_void_and_implied_elements() {
+        assert_eq!(
+            convert("<ul><li>a<li>b<br></ul>"),
+            "<ul><li>a</li><li>b<br/></li></ul>"
+        );
+        assert_eq!(convert("<p>one<p>two"), "<p>one</p><p>two</p>");
+    }
+
+    #[test]
+    fn quotes_attributes() {
+        assert_eq!(
+            convert("<input type=checkbox disabled value='a\"b'>"),
+            "<input type=\"checkbox\" disabled=\"\" value=\"a&quot;b\"/>"
+        );
+    }
+
+    #[test]
+    fn converts_character_references() {
+        assert_eq!(convert("a&nbsp;b &amp; c & d"), "a&#160;b &amp; c &amp; d");
+    }
+
+    #[test]
+    fn escapes_raw_text() {
+        assert_eq!(
+            convert("<script>if (a < b && c) {}</script>"),
+            "<script>if (a &lt; b &amp;&amp; c) {}</script>"
+        );
+    }
+
+    #[test]
+    fn goodweb_elements_are_void() {
+        assert_eq!(
+            convert("<main><GoodWeb-Inner>\n<footer>foot</footer></main>"),
+            "<main><GoodWeb-Inner/>\n<footer>foot</footer></main>"
+        );
+    }
+
+    #[test]
+    fn unterminated_quote_is_an_error() {
+        let error = to_xml("<p>Hi\n<a href=\"foo>link</p>").unwrap_err();
+        assert_eq!(error.line, 2);
+
+        assert!(to_xml("<a href='foo").is_err());
+    }
+
+    #[test]
+    fn unclosed_comment_is_an_error() {
+        let error = to_xml("<p>a</p>\n\n<!-- never closed <p>b</p>").unwrap_err();
+        assert_eq!(error.line, 3);
+
+        assert!(to_xml("<![CDATA[ a").is_err());
+    }
+
+    #[test]
+    fn lone_less_than_is_text() {
+        assert_eq!(convert("a < b <"), "a &lt; b &lt;");
+        assert_eq!(convert("<p>1 <2</p>"), "<p>1 &lt;2</p>");
+    }
+
+    #[test]
+    fn stray_end_tag_is_ignored() {
+        assert_eq!(convert("<p>a</x>b</p></br>"), "<p>ab</p>");
+        assert_eq!(convert("</"), "");
+    }
+
+    #[test]
+    fn rewrites_only_attributes() {
+        let html = "<p class=a title='x'>a class=\"b\"</p><br class = \"c\"/><!-- <i class=d> -->";
+        let rewritten = rewrite_attributes(html, &mut |name, value| {
+            (name == "class").then(|| value.to_uppercase())
+        });
+
+        assert_eq!(
+            rewritten,
+            "<p class=\"A\" title='x'>a class=\"b\"</p><br class = \"C\"/><!-- <i class=d> -->"
+        );
+        assert_eq!(
+            rewrite_attributes("<a class=\"never-closed>", &mut |_, _| Some(String::new())),
+            "<a class=\"never-closed>"
+        );
+    }
+
+    #[test]
+    fn parses_html_into_elements() {
+        let nodes = to_nodes("text <ul class=list><li>a<li id=\"b\">b</ul>").unwrap();
+
+        assert_eq!(nodes.len(), 1);
+        match &nodes[0] {
+            HtmlNode::Element(element) => {
+                assert_eq!(element.name, "ul");
+                assert_eq!(element.attribute("class"), Some("list"));
+                assert_eq!(element.children.len(), 2);
+            }
+            _ => panic!("expected an element"),
+        }
+
+        assert_eq!(to_nodes("just text"), Some(Vec::new()));
+        assert_eq!(to_nodes("<a href=\"never-closed>"), None);
+    }
+
+    #[test]
+    fn unfinished_tags_dont_panic() {
+        assert_eq!(convert("<p"), "<p></p>");
+        assert_eq!(convert("<p class"), "<p class=\"\"></p>");
+        assert_eq!(convert("<p class="), "<p class=\"\"></p>");
+        assert_eq!(convert("<br/"), "<br/>");
+    }
+}
@@ -0,0 +1,404 @@
+use crate::templating::escape_html_into;
+use serde::Deserialize;
+
+/// How pages are written to disk.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// HTML5, with a doctype, void elements and boolean attributes.
+    Html5,
+    /// Well-formed XML, where empty elements are self-closed.
+    Xml,
+}
+
+impl std::default::Default for OutputMode {
+    #[inline]
+    fn default() -> OutputMode {
+        OutputMode::Html5
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HtmlNode {
+    Element(HtmlElement),
+    /// Plain text, which is escaped when it's written.
+    Text(String),
+    /// HTML that's already been escaped, and is written as-is.
+    Html(String),
+    Comment(String),
+    /// Marks a spot that's filled in once the whole page is known.
+    Placeholder(Placeholder),
+}
+
+#[cfg(test)]
+impl HtmlNode {
+    /// An element with the given (already escaped) attributes, for building documents in tests.
+    pub fn element(name: &str, attributes: &[(&str, &str)], children: Vec<HtmlNode>) -> HtmlNode {
+        HtmlNode::Element(HtmlElement {
+            name: name.to_owned(),
+            attributes: attributes
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            children,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Placeholder {
+    HeadOutlet,
+    /// `<GoodWeb-Styles/>`, which links to the stylesheets once they're known.
+    Styles,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HtmlElement {
+    pub name: String,
+    /// The values of attributes are already escaped, like `HtmlNode::Html`.
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<HtmlNode>,
+}
+
+impl HtmlElement {
+    #[inline]
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Builds up the nodes of a page, in the same order they'd be written in.
+pub struct HtmlWriter {
+    nodes: Vec<HtmlNode>,
+    open_elements: Vec<HtmlElement>,
+}
+
+impl HtmlWriter {
+    #[inline]
+    pub fn new() -> HtmlWriter {
+        HtmlWriter {
+            nodes: Vec::new(),
+            open_elements: Vec::new(),
+        }
+    }
+
+    pub fn start_element(&mut self, name: &str) {
+        self.open_elements.push(HtmlElement {
+            name: name.to_owned(),
+            attributes: Vec::new(),
+            children: Vec::new(),
+        });
+    }
+
+    /// Writes an attribute to the element that was last started. Writing the same attribute twice
+    /// replaces its value.
+    pub fn write_attribute(&mut self, name: &str, value: &str) {
+        let element = self
+            .open_elements
+            .last_mut()
+            .expect("must be called after start_element()");
+
+        match element
+            .attributes
+            .iter_mut()
+            .find(|(attribute, _)| attribute == name)
+        {
+            Some((_, existing)) => *existing = value.to_owned(),
+            None => element.attributes.push((name.to_owned(), value.to_owned())),
+        }
+    }
+
+    /// Adds to the value of an attribute of the element that was last started, putting the
+    /// separator between the old and new value if it's not there already.
+    pub fn append_attribute(&mut self, name: &str, value: &str, separator: &str) {
+        let element = self
+            .open_elements
+            .last_mut()
+            .expect("must be called after start_element()");
+
+        match element
+            .attributes
+            .iter_mut()
+            .find(|(attribute, _)| attribute == name)
+        {
+            Some((_, existing)) if !existing.trim().is_empty() => {
+                let trimmed_len = existing.trim_end().len();
+                existing.truncate(trimmed_len);
+
+                if !existing.ends_with(separator) {
+                    existing.push_str(separator);
+                }
+
+                existing.push_str(value);
+            }
+            _ => self.write_attribute(name, value),
+        }
+    }
+
+    #[inline]
+    pub fn write_text(&mut self, text: &str) {
+        self.push(HtmlNode::Text(text.to_owned()));
+    }
+
+    #[inline]
+    pub fn write_html(&mut self, html: &str) {
+        self.push(HtmlNode::Html(html.to_owned()));
+    }
+
+    #[inline]
+    pub fn write_comment(&mut self, comment: &str) {
+        self.push(HtmlNode::Comment(comment.to_owned()));
+    }
+
+    #[inline]
+    pub fn write_placeholder(&mut self, placeholder: Placeholder) {
+        self.push(HtmlNode::Placeholder(placeholder));
+    }
+
+    /// Whether any of the elements that are currently open have one of the given names.
+    pub fn is_inside(&self, names: &[&str]) -> bool {
+        self.open_elements
+            .iter()
+            .any(|element| names.contains(&element.name.as_str()))
+    }
+
+    pub fn end_element(&mut self) {
+        let element = self
+            .open_elements
+            .pop()
+            .expect("must be called after start_element()");
+
+        if is_void_element(&element.name) && !element.children.is_empty() {
+            println!(
+                "[WARN] <{}> can't have any children in HTML5, they won't be output",
+                element.name
+            );
+        }
+
+        self.push(HtmlNode::Element(element));
+    }
+
+    /// Closes any elements which are still open, and returns every node that was written.
+    pub fn end_document(mut self) -> Vec<HtmlNode> {
+        while !self.open_elements.is_empty() {
+            self.end_element();
+        }
+
+        self.nodes
+    }
+
+    #[inline]
+    fn push(&mut self, node: HtmlNode) {
+        match self.open_elements.last_mut() {
+            Some(element) => element.children.push(node),
+            None => self.nodes.push(node),
+        }
+    }
+}
+
+/// Replaces every placeholder of a kind with the given nodes.
+pub fn fill_placeholder(nodes: &mut Vec<HtmlNode>, placeholder: Placeholder, with: &[HtmlNode]) {
+    let mut index = 0;
+
+    while index < nodes.len() {
+        match &mut nodes[index] {
+            HtmlNode::Placeholder(found) if *found == placeholder => {
+                nodes.splice(index..index + 1, with.iter().cloned());
+                index += with.len();
+                continue;
+            }
+            HtmlNode::Element(element) => {
+                fill_placeholder(&mut element.children, placeholder, with)
+            }
+            _ => (),
+        }
+
+        index += 1;
+    }
+}
+
+pub fn serialize(nodes: &[HtmlNode], mode: OutputMode) -> String {
+    // no idea how much we'll need, but let's allocate a pretty large buffer just in case
+    let mut output = String::with_capacity(1_000);
+
+    if mode == OutputMode::Html5 {
+        output.push_str("<!DOCTYPE html>");
+    }
+
+    for node in nodes {
+        serialize_node(&mut output, node, mode, false);
+    }
+
+    output
+}
+
+fn serialize_node(output: &mut String, node: &HtmlNode, mode: OutputMode, raw_text: bool) {
+    match node {
+        HtmlNode::Element(element) => serialize_element(output, element, mode),
+        HtmlNode::Text(text) => {
+            if raw_text {
+                escape_raw_text(output, text);
+            } else {
+                escape_html_into(output, text);
+            }
+        }
+        HtmlNode::Html(html) => output.push_str(html),
+        HtmlNode::Comment(comment) => {
+            output.push_str("<!--");
+            // a computed comment could otherwise end itself early
+            output.push_str(&comment.replace("-->", "-- >"));
+            output.push_str("-->");
+        }
+        // anything that was never filled in just isn't output
+        HtmlNode::Placeholder(_) => (),
+    }
+}
+
+fn serialize_element(output: &mut String, element: &HtmlElement, mode: OutputMode) {
+    output.push('<');
+    output.push_str(&element.name);
+
+    for (name, value) in element.attributes.iter() {
+        output.push(' ');
+        output.push_str(name);
+
+        // boolean attributes are on by being present, e.g. `disabled` or `disabled="disabled"`
+        if mode == OutputMode::Html5
+            && is_boolean_attribute(name)
+            && (value.is_empty() || value.eq_ignore_ascii_case(name))
+        {
+            continue;
+        }
+
+        output.push_str("=\"");
+        escape_attribute(output, value);
+        output.push('"');
+    }
+
+    match mode {
+        OutputMode::Html5 => {
+            output.push('>');
+
+            if is_void_element(&element.name) {
+                return;
+            }
+        }
+        OutputMode::Xml => {
+            if element.children.is_empty() {
+                output.push_str("/>");
+                return;
+            }
+
+            output.push('>');
+        }
+    }
+
+    let raw_text = mode == OutputMode::Html5 && is_raw_text_element(&element.name);
+    for child in element.children.iter() {
+        serialize_node(output, child, mode, raw_text);
+    }
+
+    output.push_str("</");
+    output.push_str(&element.name);
+    output.push('>');
+}
+
+/// The contents of <script> and <style> can't be escaped, but they can't contain their end tag.
+fn escape_raw_text(output: &mut String, text: &str) {
+    let mut rest = text;
+
+    while let Some(index) = rest.find("</") {
+        output.push_str(&rest[..index]);
+
+        let after = &rest[index + 2..];
+        let is_end_tag = ["script", "style"].iter().any(|name| {
+            after
+                .get(..name.len())
+                .is_some_and(|tag| tag.eq_ignore_ascii_case(name))
+        });
+
+        if is_end_tag {
+            output.push_str("<\\/");
+        } else {
+            output.push_str("</");
+        }
+
+        rest = after;
+    }
+
+    output.push_str(rest);
+}
+
+/// Attribute values have already been escaped by the template engine, this makes sure nothing
+/// that was trusted with `{{{ }}}` can break out of the attribute.
+fn escape_attribute(output: &mut String, value: &str) {
+    for character in value.chars() {
+        match character {
+            '"' => output.push_str("&quot;"),
+            _ => output.push(character),
+        }
+    }
+}
+
+/// Elements which never have any children, and so never have an end tag.
+#[inline]
+pub fn is_void_element(name: &str) -> bool {
+    matches!(
+        name,
+        "area"
+            | "base"
+            | "br"
+            | "col"
+            | "embed"
+            | "hr"
+            | "img"
+            | "input"
+            | "link"
+            | "meta"
+            | "param"
+            | "source"
+            | "track"
+            | "wbr"
+    )
+}
+
+/// Elements whose text is written exactly as-is.
+#[inline]
+pub fn is_raw_text_element(name: &str) -> bool {
+    matches!(name, "script" | "style")
+}
+
+#[inline]
+pub fn is_boolean_attribute(name: &str) -> bool {
+    matches!(
+        name,
+        "allowfullscreen"
+            | "async"
+            | "autofocus"
+            | "autoplay"
+            | "checked"
+            | "controls"
+            | "default"
+            | "defer"
+            | "disabled"
+            | "formnovalidate"
+            | "hidden"
+            | "inert"
+            | "ismap"
+            | "itemscope"
+            | "loop"
+            | "multiple"
+            | "muted"
+            | "nomodule"
+            | "novalidate"
+            | "open"
+            | "playsinline"
+            | "readonly"
+            | "required"
+            | "reversed"
+            | "selected"
+    )
+}
@@ -2,17 +2,37 @@ extern crate handlebars;
 extern crate roxmltree;
 extern crate walkdir;
 
+mod config;
+mod css;
+mod css_assets;
+mod css_imports;
+mod css_minifier;
+mod css_nesting;
+mod css_pruner;
+mod css_renamer;
+mod html_parser;
+mod html_writer;
+mod packages;
 mod page_builder;
 mod templating;
 mod website_parser;
 
+use css::{CssNode, Stylesheet};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
 
+/// CSS used by many pages is put in here, when it's shared.
+const SHARED_STYLESHEET: &str = "goodweb-shared.css";
+
+/// Where the hashed names of stylesheets are written to, keyed by their usual name.
+const MANIFEST_FILE: &str = "build/manifest.json";
+
 fn main() {
     match main_option() {
-        Some(_) => return,
+        Some(_) => (),
         None => println!("Exited on error."),
     };
 }
@@ -27,82 +47,377 @@ fn main_option() -> Option<()> {
         std::env::current_dir().unwrap().display()
     );
 
+    let update_lock = std::env::args().any(|argument| argument == "--update-lock");
+    // release builds are as small as possible, rather than readable
+    let release = std::env::args().any(|argument| argument == "--release");
+    let config = config::load_config()?;
+
     ensure_build_exists()?;
 
     println!("parsing components...");
-    let component_store = website_parser::compute_components(Path::new("components"))?;
+    let mut component_store = website_parser::compute_components(Path::new("components"))?;
+
+    for theme in config.themes.iter() {
+        println!("parsing theme '{}'...", theme);
+        let theme_directory = Path::new(theme).join("components");
+
+        if !theme_directory.is_dir() {
+            println!("[ERR] theme '{}' has no components directory", theme);
+            return None;
+        }
+
+        component_store.inherit(website_parser::compute_components(&theme_directory)?);
+    }
+
+    println!("loading packages...");
+    packages::load_packages(&config, &mut component_store, update_lock)?;
+
+    warn_css_without_xml("component", &component_store);
+    component_store.scope_styles();
+    let documents = page_builder::ComponentDocuments::parse(&component_store);
 
     println!("parsing pages...");
     let pages = website_parser::compute_components(Path::new("pages"))?;
+    warn_css_without_xml("page", &pages);
 
     println!("building pages...");
-    for (key, page) in pages.components.iter() {
+    // pages are built in the same order every time, so names are shortened the same way too
+    let mut pages_sorted = pages.components.iter().collect::<Vec<_>>();
+    pages_sorted.sort_by_key(|(key, _)| *key);
+
+    let mut built = Vec::new();
+    for (key, page) in pages_sorted {
+        // there's nothing to build, it's already been warned about
+        if !page.has_xml() {
+            continue;
+        }
+
         println!("building '{}'", key);
-        let result = page_builder::build_page(key.clone(), page, &component_store)?;
+        let result =
+            page_builder::build_page(key.clone(), page, &component_store, &documents, &config)?;
+        built.push((key, page, result));
+    }
 
-        let mut html_name = String::from("build/");
-        html_name.push_str(key);
-        html_name.push_str(".html");
+    let mut renamer = css_renamer::Renamer::new(&config.css);
+    let mut assets = css_assets::Assets::default();
 
-        let component_path = Path::new(&html_name);
-        let mut file = match File::create(component_path) {
-            Ok(file) => file,
-            Err(_) => {
-                println!("[ERR] can't open file '{}'", component_path.display());
-                continue;
+    let shared_components = match &config.css.shared {
+        Some(shared) => find_shared_components(&component_store, &built, shared.pages)?,
+        None => Vec::new(),
+    };
+
+    let uses_shared = |result: &page_builder::BuildResult| {
+        result
+            .components_used()
+            .iter()
+            .any(|component| shared_components.contains(component))
+    };
+
+    // with `hash`, the name each stylesheet is written as, e.g. `index.css` as `index.1a2b3c4d.css`
+    let mut manifest = config.css.hash.then(BTreeMap::new);
+
+    // the tokens go wherever a page gets its CSS first, so that everything after can use them
+    let tokens = tokens_rule(&config);
+
+    // the shared CSS is pruned first, while every page still has its original names
+    let mut shared_stylesheet = Stylesheet::default();
+    let mut shared_css = String::new();
+    let mut shared_file = String::new();
+    if !shared_components.is_empty() {
+        println!("building shared css...");
+        let mut stylesheet = collect_stylesheet(&component_store, &shared_components, &mut assets)?;
+        prepend_rule(&mut stylesheet, tokens.as_ref());
+
+        if config.css.prune {
+            let documents = built
+                .iter()
+                .filter(|(_, _, result)| uses_shared(result))
+                .map(|(_, _, result)| result.document())
+                .collect::<Vec<_>>();
+
+            if css_pruner::prune(&mut stylesheet, &documents, &config.css.safelist).is_none() {
+                println!("[WARN] a page using the shared CSS has HTML that couldn't be read, so the shared CSS isn't pruned");
             }
+        }
+
+        renamer.rename_stylesheet(&mut stylesheet);
+
+        if release {
+            css_minifier::minify(&mut stylesheet);
+        }
+
+        shared_css = stylesheet.write(release);
+        if !shared_css.is_empty() {
+            shared_file = stylesheet_file(SHARED_STYLESHEET, &shared_css, &mut manifest);
+            write_build_file(&format!("build/{}", shared_file), &shared_css);
+        }
+
+        shared_stylesheet = stylesheet;
+    }
+
+    for (key, page, mut result) in built {
+        println!("writing '{}'", key);
+
+        let components = result
+            .components_used()
+            .iter()
+            .filter(|component| !shared_components.contains(component))
+            .copied()
+            .collect::<Vec<_>>();
+
+        let mut stylesheet = collect_stylesheet(&component_store, &components, &mut assets)?;
+
+        // the page's own CSS comes last, so that it can override its components
+        append_stylesheet(&mut stylesheet, page, &mut assets);
+
+        if shared_css.is_empty() || !uses_shared(&result) {
+            prepend_rule(&mut stylesheet, tokens.as_ref());
+        }
+
+        let pruned = !config.css.prune
+            || css_pruner::prune(&mut stylesheet, &[result.document()], &config.css.safelist)
+                .is_some();
+
+        if !pruned {
+            println!(
+                "[WARN] page '{}' has HTML that couldn't be read, so its CSS isn't pruned",
+                key
+            );
+        }
+
+        if renamer.is_enabled() {
+            renamer.rename_document(result.document_mut());
+            renamer.rename_stylesheet(&mut stylesheet);
+        }
+
+        if release {
+            css_minifier::minify(&mut stylesheet);
+        }
+
+        let page_css = stylesheet.write(release);
+        let shared_css = if uses_shared(&result) {
+            shared_css.as_str()
+        } else {
+            ""
         };
 
-        match file.write(result.xml().as_bytes()) {
-            Ok(_) => (),
-            Err(_) => {
+        let inline = match result.inline_styles() {
+            Some(limit) if shared_css.len() + page_css.len() <= limit => true,
+            Some(limit) => {
                 println!(
-                    "[ERR] couldn't write xml to file '{}'",
-                    component_path.display()
+                    "[WARN] page '{}' has {} bytes of CSS, more than the {} that may be inlined - linking to it instead",
+                    key,
+                    shared_css.len() + page_css.len(),
+                    limit
                 );
+                false
             }
+            None => false,
         };
 
-        let mut css_name = String::from("build/");
-        css_name.push_str(key);
-        css_name.push_str(".css");
-
-        let component_path = Path::new(&css_name);
-        let mut file = match File::create(component_path) {
-            Ok(file) => file,
-            Err(_) => {
-                println!(
-                    "[ERR] couldn't write css to file '{}'",
-                    component_path.display()
-                );
-                continue;
+        if inline {
+            if shared_css.is_empty() {
+                result.embed_styles(&page_css);
+            } else {
+                // the page's imports have to come before the shared rules as well
+                let mut joined = Stylesheet {
+                    nodes: shared_stylesheet.nodes.clone(),
+                };
+                joined.nodes.extend(stylesheet.nodes.iter().cloned());
+                css_imports::hoist_imports(&mut joined.nodes);
+                result.embed_styles(&joined.write(release));
+            }
+        } else {
+            let mut stylesheets = Vec::new();
+            if !shared_css.is_empty() {
+                stylesheets.push(shared_file.clone());
             }
-        };
 
-        for component_used in result.components_used() {
-            let component = component_store.find_component_by_id(*component_used)?;
+            // there's no need to download an empty stylesheet if everything is shared
+            if stylesheets.is_empty() || !page_css.is_empty() {
+                let page_file = stylesheet_file(&format!("{}.css", key), &page_css, &mut manifest);
+                write_build_file(&format!("build/{}", page_file), &page_css);
+                stylesheets.push(page_file);
+            }
 
-            match file.write(component.css_data().as_bytes()) {
-                Ok(_) => (),
-                Err(_) => {
-                    println!(
-                        "[ERR] couldn't write css to file '{}'",
-                        component_path.display()
-                    );
-                }
-            };
+            result.link_styles(&stylesheets);
         }
+
+        let html = html_writer::serialize(result.document(), config.output);
+        write_build_file(&format!("build/{}.html", key), &html);
+    }
+
+    if renamer.is_enabled() {
+        renamer.write()?;
+    }
+
+    if let Some(manifest) = manifest {
+        let mut data = serde_json::to_string_pretty(&manifest).ok()?;
+        data.push('\n');
+        write_build_file(MANIFEST_FILE, &data);
     }
 
     Some(())
 }
 
+/// Finds the components with CSS that are used by at least `min_pages` pages, in the order they're
+/// first used in.
+fn find_shared_components(
+    component_store: &page_builder::ComponentStore,
+    built: &[(&String, &page_builder::Component, page_builder::BuildResult)],
+    min_pages: usize,
+) -> Option<Vec<i32>> {
+    let mut uses: Vec<(i32, usize)> = Vec::new();
+
+    for (_, _, result) in built {
+        for component in result.components_used() {
+            match uses.iter_mut().find(|(id, _)| id == component) {
+                Some((_, count)) => *count += 1,
+                None => uses.push((*component, 1)),
+            }
+        }
+    }
+
+    let mut shared = Vec::new();
+    for (id, count) in uses {
+        let component = component_store.find_component_by_id(id)?;
+
+        if count >= min_pages && !component.stylesheet().nodes.is_empty() {
+            shared.push(id);
+        }
+    }
+
+    Some(shared)
+}
+
+/// The name a stylesheet is written as. If there's a manifest, the name has a hash of the CSS in it
+/// so that it can be cached forever, and it's recorded in the manifest.
+fn stylesheet_file(
+    name: &str,
+    css: &str,
+    manifest: &mut Option<BTreeMap<String, String>>,
+) -> String {
+    let manifest = match manifest {
+        Some(manifest) => manifest,
+        None => return name.to_owned(),
+    };
+
+    let hash = format!("{:x}", Sha256::digest(css.as_bytes()));
+    let stem = name.strip_suffix(".css").unwrap_or(name);
+    let file = format!("{}.{}.css", stem, &hash[..8]);
+
+    manifest.insert(name.to_owned(), file.clone());
+    file
+}
+
+/// A `:root` rule with every token in the config as a custom property, if there are any.
+fn tokens_rule(config: &config::Config) -> Option<CssNode> {
+    let properties = config.token_properties();
+    if properties.is_empty() {
+        return None;
+    }
+
+    Some(CssNode::Rule {
+        selectors: css::parse_selectors(":root")?,
+        children: properties
+            .into_iter()
+            .map(|(property, value)| CssNode::Declaration { property, value })
+            .collect(),
+    })
+}
+
+/// Puts a rule at the start of a stylesheet, after any `@import`s since they have to come first.
+fn prepend_rule(stylesheet: &mut Stylesheet, rule: Option<&CssNode>) {
+    let rule = match rule {
+        Some(rule) => rule,
+        None => return,
+    };
+
+    let index = stylesheet
+        .nodes
+        .iter()
+        .position(|node| !matches!(node, CssNode::AtRule { name, .. } if name == "import" || name == "charset"))
+        .unwrap_or(stylesheet.nodes.len());
+
+    stylesheet.nodes.insert(index, rule.clone());
+}
+
+/// Puts the CSS of every component into one stylesheet, in order. The files their CSS refers to
+/// are copied into the build.
+fn collect_stylesheet(
+    component_store: &page_builder::ComponentStore,
+    components: &[i32],
+    assets: &mut css_assets::Assets,
+) -> Option<Stylesheet> {
+    let mut stylesheet = Stylesheet::default();
+
+    for component in components {
+        let component = component_store.find_component_by_id(*component)?;
+        append_stylesheet(&mut stylesheet, component, assets);
+    }
+
+    Some(stylesheet)
+}
+
+fn append_stylesheet(
+    stylesheet: &mut Stylesheet,
+    component: &page_builder::Component,
+    assets: &mut css_assets::Assets,
+) {
+    let mut nodes = component.stylesheet().nodes.clone();
+
+    if let Some(css_path) = component.css_path() {
+        assets.rewrite_urls(&mut nodes, css_path);
+    }
+
+    stylesheet.nodes.extend(nodes);
+    css_imports::hoist_imports(&mut stylesheet.nodes);
+}
+
+fn write_build_file(name: &str, data: &str) {
+    let path = Path::new(name);
+    let mut file = match File::create(path) {
+        Ok(file) => file,
+        Err(_) => {
+            println!("[ERR] can't open file '{}'", path.display());
+            return;
+        }
+    };
+
+    if file.write_all(data.as_bytes()).is_err() {
+        println!("[ERR] couldn't write to file '{}'", path.display());
+    }
+}
+
+/// CSS is only ever included alongside the XML of the same name, so CSS on its own is unused.
+fn warn_css_without_xml(kind: &str, store: &page_builder::ComponentStore) {
+    let mut names = store
+        .components
+        .iter()
+        .filter(|(_, component)| !component.has_xml())
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>();
+    names.sort();
+
+    for name in names {
+        println!(
+            "[WARN] {} '{}' has CSS, but no XML - its CSS won't be used",
+            kind, name
+        );
+    }
+}
+
 fn ensure_build_exists() -> Option<()> {
     delete_build()?;
 
     let directory = Path::new("build");
-    std::fs::create_dir(directory);
-    Some(())
+    match std::fs::create_dir(directory) {
+        Ok(_) => Some(()),
+        Err(_) => {
+            println!("couldn't create 'build'.");
+            None
+        }
+    }
 }
 
 fn delete_build() -> Option<()> {
@@ -0,0 +1,471 @@
+use crate::config::{Config, PackageSource};
+use crate::page_builder::ComponentStore;
+use crate::website_parser;
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// The lockfile records the exact version and hash of every package that was used.
+pub const LOCK_FILE: &str = "goodweb.lock";
+
+/// Every package has a manifest at its root describing what it is.
+const PACKAGE_MANIFEST: &str = "goodweb-package.json";
+
+/// Archives are unpacked in here before their components are read.
+const UNPACK_DIRECTORY: &str = ".goodweb/packages";
+
+#[derive(Deserialize)]
+struct PackageManifest {
+    name: String,
+    version: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Lockfile {
+    packages: BTreeMap<String, LockedPackage>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+struct LockedPackage {
+    name: String,
+    source: String,
+    version: String,
+    hash: String,
+}
+
+/// Loads every package in the config into the component store, under the namespace it's
+/// configured with. The packages are checked against the lockfile, and unless `update_lock` is
+/// set, a package that no longer matches its locked version or hash is an error.
+pub fn load_packages(
+    config: &Config,
+    component_store: &mut ComponentStore,
+    update_lock: bool,
+) -> Option<()> {
+    let old_lock = read_lockfile()?;
+    let mut new_lock = Lockfile::default();
+    let mut has_errors = false;
+
+    for (namespace, source) in config.packages.iter() {
+        // the namespace names a directory, and is put in front of the names of components
+        if !is_valid_namespace(namespace) {
+            println!(
+                "[ERR] package name '{}' can only have letters, digits and underscores",
+                namespace
+            );
+            has_errors = true;
+            continue;
+        }
+
+        println!("loading package '{}'...", namespace);
+
+        let locked = match load_package(namespace, source, component_store) {
+            Some(locked) => locked,
+            None => {
+                has_errors = true;
+                continue;
+            }
+        };
+
+        if !matches_lock(
+            namespace,
+            old_lock.packages.get(namespace),
+            &locked,
+            update_lock,
+        ) {
+            has_errors = true;
+            continue;
+        }
+
+        new_lock.packages.insert(namespace.clone(), locked);
+    }
+
+    if has_errors {
+        return None;
+    }
+
+    // don't touch the lockfile of websites which don't use packages
+    if new_lock.packages.is_empty() && old_lock.packages.is_empty() {
+        return Some(());
+    }
+
+    write_lockfile(&new_lock)
+}
+
+/// Whether a package is what the lockfile says it was, or it's allowed to be different.
+fn matches_lock(
+    namespace: &str,
+    previous: Option<&LockedPackage>,
+    locked: &LockedPackage,
+    update_lock: bool,
+) -> bool {
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return true,
+    };
+
+    if update_lock || previous == locked {
+        return true;
+    }
+
+    println!(
+        "[ERR] package '{}' doesn't match '{}' - locked '{}' {} ({}), found '{}' {} ({})",
+        namespace,
+        LOCK_FILE,
+        previous.name,
+        previous.version,
+        previous.hash,
+        locked.name,
+        locked.version,
+        locked.hash
+    );
+    println!("      run with '--update-lock' if this change is expected");
+    false
+}
+
+fn load_package(
+    namespace: &str,
+    source: &PackageSource,
+    component_store: &mut ComponentStore,
+) -> Option<LockedPackage> {
+    let path = Path::new(&source.path);
+
+    let (root, hash) = if path.is_dir() {
+        (path.to_path_buf(), hash_directory(path)?)
+    } else if path.is_file() {
+        unpack_archive(namespace, path)?
+    } else {
+        println!(
+            "[ERR] package '{}' not found at '{}'",
+            namespace,
+            path.display()
+        );
+        return None;
+    };
+
+    let root = find_package_root(&root)?;
+    let manifest_path = root.join(PACKAGE_MANIFEST);
+    let manifest: PackageManifest = match std::fs::read_to_string(&manifest_path)
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+    {
+        Some(manifest) => manifest,
+        None => {
+            println!(
+                "[ERR] couldn't read package manifest '{}'",
+                manifest_path.display()
+            );
+            return None;
+        }
+    };
+
+    if let Some(version) = &source.version {
+        if version != &manifest.version {
+            println!(
+                "[ERR] package '{}' is version {}, but version {} is required",
+                namespace, manifest.version, version
+            );
+            return None;
+        }
+    }
+
+    let components_directory = root.join("components");
+    if components_directory.is_dir() {
+        website_parser::compute_components_into(
+            component_store,
+            &components_directory,
+            Some(namespace),
+        )?;
+    }
+
+    Some(LockedPackage {
+        name: manifest.name,
+        source: source.path.clone(),
+        version: manifest.version,
+        hash,
+    })
+}
+
+#[inline]
+fn is_valid_namespace(namespace: &str) -> bool {
+    !namespace.is_empty()
+        && namespace
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || character == '_')
+}
+
+/// Unpacks an archive into its own directory, returning that directory and the hash of the
+/// archive.
+fn unpack_archive(namespace: &str, path: &Path) -> Option<(PathBuf, String)> {
+    let destination = Path::new(UNPACK_DIRECTORY).join(namespace);
+    if destination.parent() != Some(Path::new(UNPACK_DIRECTORY)) {
+        println!(
+            "[ERR] package '{}' would be unpacked outside of '{}'",
+            namespace, UNPACK_DIRECTORY
+        );
+        return None;
+    }
+
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(_) => {
+            println!("[ERR] couldn't read package '{}'", path.display());
+            return None;
+        }
+    };
+
+    let hash = format!("sha256-{:x}", Sha256::digest(&data));
+
+    if destination.exists() && std::fs::remove_dir_all(&destination).is_err() {
+        println!("[ERR] couldn't cleanup '{}'", destination.display());
+        return None;
+    }
+
+    let file_name = path.file_name()?.to_str()?;
+    let result = match unpack(&data, file_name, &destination) {
+        Some(result) => result,
+        None => {
+            println!(
+                "[ERR] unsupported package archive '{}' - expected '.tar', '.tar.gz' or '.tgz'",
+                path.display()
+            );
+            return None;
+        }
+    };
+
+    match result {
+        Ok(_) => Some((destination, hash)),
+        Err(error) => {
+            println!("[ERR] couldn't unpack '{}': {}", path.display(), error);
+            None
+        }
+    }
+}
+
+/// Unpacks a `.tar`, `.tar.gz` or `.tgz` archive, or returns `None` for anything else. Entries
+/// which would end up outside of the destination, like `../file`, are skipped.
+fn unpack(data: &[u8], file_name: &str, destination: &Path) -> Option<std::io::Result<()>> {
+    if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+        Some(tar::Archive::new(GzDecoder::new(data)).unpack(destination))
+    } else if file_name.ends_with(".tar") {
+        Some(tar::Archive::new(data).unpack(destination))
+    } else {
+        None
+    }
+}
+
+/// Archives commonly wrap everything in a single directory, so the manifest may be one level down.
+fn find_package_root(directory: &Path) -> Option<PathBuf> {
+    if directory.join(PACKAGE_MANIFEST).is_file() {
+        return Some(directory.to_path_buf());
+    }
+
+    let mut entries = std::fs::read_dir(directory)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path());
+
+    if let (Some(only), None) = (entries.next(), entries.next()) {
+        if only.join(PACKAGE_MANIFEST).is_file() {
+            return Some(only);
+        }
+    }
+
+    println!(
+        "[ERR] no '{}' found in package '{}'",
+        PACKAGE_MANIFEST,
+        directory.display()
+    );
+    None
+}
+
+/// Hashes the path and contents of every file in a directory, in a stable order.
+fn hash_directory(directory: &Path) -> Option<String> {
+    let mut hasher = Sha256::new();
+    let walker = WalkDir::new(directory).sort_by(|a, b| a.file_name().cmp(b.file_name()));
+
+    for file in walker.into_iter() {
+        let file = file.ok()?;
+
+        if file.file_type().is_dir() {
+            continue;
+        }
+
+        let relative = file.path().strip_prefix(directory).ok()?;
+        for part in relative.iter() {
+            hasher.update(part.to_str()?.as_bytes());
+            hasher.update(b"/");
+        }
+
+        let mut data = Vec::new();
+        if File::open(file.path())
+            .and_then(|mut file| file.read_to_end(&mut data))
+            .is_err()
+        {
+            println!("[ERR] couldn't read '{}'", file.path().display());
+            return None;
+        }
+
+        hasher.update((data.len() as u64).to_le_bytes());
+        hasher.update(&data);
+    }
+
+    Some(format!("sha256-{:x}", hasher.finalize()))
+}
+
+fn read_lockfile() -> Option<Lockfile> {
+    let path = Path::new(LOCK_FILE);
+
+    if !path.exists() {
+        return Some(Lockfile::default());
+    }
+
+    match std::fs::read_to_string(path)
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+    {
+        Some(lockfile) => Some(lockfile),
+        None => {
+            println!("[ERR] couldn't read lockfile '{}'", path.display());
+            None
+        }
+    }
+}
+
+fn write_lockfile(lockfile: &Lockfile) -> Option<()> {
+    let mut data = serde_json::to_string_pretty(lockfile).ok()?;
+    data.push('\n');
+
+    match std::fs::write(LOCK_FILE, data) {
+        Ok(_) => Some(()),
+        Err(_) => {
+            println!("[ERR] couldn't write lockfile '{}'", LOCK_FILE);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own for a test, which is emptied first.
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("goodweb-packages-{}", name));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn locked(version: &str, hash: &str) -> LockedPackage {
+        LockedPackage {
+            name: "bootstrap".to_owned(),
+            source: "../packages/bootstrap.tar.gz".to_owned(),
+            version: version.to_owned(),
+            hash: hash.to_owned(),
+        }
+    }
+
+    /// A tar archive with a single file in it. The name is written as-is, so it can be one that
+    /// `tar` wouldn't let us add, like `../file`.
+    fn archive(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append(&header, data).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn namespaces_are_plain_names() {
+        assert!(is_valid_namespace("bootstrap"));
+        assert!(is_valid_namespace("ui_kit2"));
+
+        for namespace in &["", "..", "../..", "/home/me", "a/b", "a.b", "ui-kit", "a b"] {
+            assert!(!is_valid_namespace(namespace), "{}", namespace);
+        }
+    }
+
+    #[test]
+    fn archives_arent_unpacked_outside_of_the_packages() {
+        // rejected before the archive is even read
+        assert!(unpack_archive("..", Path::new("missing.tar")).is_none());
+        assert!(unpack_archive("a/../..", Path::new("missing.tar")).is_none());
+    }
+
+    #[test]
+    fn entries_outside_of_the_archive_are_skipped() {
+        let directory = test_directory("escape");
+        let destination = directory.join("package");
+
+        let data = archive("../escaped.txt", b"oops");
+        unpack(&data, "package.tar", &destination).unwrap().unwrap();
+        assert!(!directory.join("escaped.txt").exists());
+
+        let data = archive("components/Button.xml", b"<button/>");
+        unpack(&data, "package.tar", &destination).unwrap().unwrap();
+        assert!(destination.join("components/Button.xml").is_file());
+
+        assert!(unpack(&data, "package.zip", &destination).is_none());
+    }
+
+    #[test]
+    fn directory_hashes_are_stable() {
+        let first = test_directory("hash-first");
+        let second = test_directory("hash-second");
+
+        // written in a different order, which the hash shouldn't depend on
+        std::fs::create_dir_all(first.join("components")).unwrap();
+        std::fs::write(first.join("goodweb-package.json"), "{}").unwrap();
+        std::fs::write(first.join("components/Button.xml"), "<button/>").unwrap();
+
+        std::fs::create_dir_all(second.join("components")).unwrap();
+        std::fs::write(second.join("components/Button.xml"), "<button/>").unwrap();
+        std::fs::write(second.join("goodweb-package.json"), "{}").unwrap();
+
+        let hash = hash_directory(&first).unwrap();
+        assert!(hash.starts_with("sha256-"));
+        assert_eq!(hash_directory(&first).unwrap(), hash);
+        assert_eq!(hash_directory(&second).unwrap(), hash);
+
+        std::fs::write(second.join("components/Button.xml"), "<button></button>").unwrap();
+        assert_ne!(hash_directory(&second).unwrap(), hash);
+
+        // moving a file changes the hash, even if the contents are the same
+        std::fs::rename(
+            first.join("components/Button.xml"),
+            first.join("components/Link.xml"),
+        )
+        .unwrap();
+        assert_ne!(hash_directory(&first).unwrap(), hash);
+    }
+
+    #[test]
+    fn packages_have_to_match_the_lockfile() {
+        let previous = locked("4.4.1", "sha256-a");
+
+        assert!(matches_lock("bootstrap", None, &previous, false));
+        assert!(matches_lock("bootstrap", Some(&previous), &previous, false));
+
+        let changed = locked("4.4.1", "sha256-b");
+        assert!(!matches_lock("bootstrap", Some(&previous), &changed, false));
+        assert!(matches_lock("bootstrap", Some(&previous), &changed, true));
+
+        let upgraded = locked("4.5.0", "sha256-a");
+        assert!(!matches_lock(
+            "bootstrap",
+            Some(&previous),
+            &upgraded,
+            false
+        ));
+        assert!(matches_lock("bootstrap", Some(&previous), &upgraded, true));
+    }
+}
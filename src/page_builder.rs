@@ -1,13 +1,31 @@
-use crate::templating::TemplateEngine;
-use roxmltree::{Attribute, Children, Document, Node, NodeType};
+use crate::config::{Config, KeepComments};
+use crate::css::{ParseError, Stylesheet};
+use crate::css_imports;
+use crate::html_writer::{
+    fill_placeholder, is_boolean_attribute, is_raw_text_element, HtmlElement, HtmlNode, HtmlWriter,
+    Placeholder,
+};
+use crate::templating::{self, escape_html_into, TemplateEngine};
+use roxmltree::{Attribute, Children, Document, Namespace, Node, NodeType};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use std::collections::HashMap;
-use xmlwriter::{Indent, Options, XmlWriter};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct Component {
     id: i32,
+    // always has a single <GoodWeb-Fragment> element at its root, which holds every top-level
+    // node of the component
     xml_data: String,
-    css_data: String,
+    stylesheet: Stylesheet,
+    has_css: bool,
+    // where the CSS was read from, which its urls are relative to
+    css_path: Option<PathBuf>,
+    // the attribute the CSS is scoped to, once it's been scoped
+    scope: Option<String>,
+    options: ComponentOptions,
     // document: Document,
 }
 
@@ -18,13 +36,19 @@ impl Component {
     }
 
     #[inline]
-    pub fn document(&self) -> Document<'_> {
-        Document::parse(self.xml_data.as_ref()).unwrap()
+    pub fn stylesheet(&self) -> &Stylesheet {
+        &self.stylesheet
     }
 
     #[inline]
-    pub fn css_data(&self) -> &String {
-        &self.css_data
+    pub fn css_path(&self) -> Option<&Path> {
+        self.css_path.as_deref()
+    }
+
+    /// Whether the component has any XML, rather than only CSS.
+    #[inline]
+    pub fn has_xml(&self) -> bool {
+        !self.xml_data.is_empty()
     }
 }
 
@@ -34,11 +58,25 @@ impl std::default::Default for Component {
         Component {
             id: -1,
             xml_data: String::new(),
-            css_data: String::new(),
+            stylesheet: Stylesheet::default(),
+            has_css: false,
+            css_path: None,
+            scope: None,
+            options: ComponentOptions::default(),
         }
     }
 }
 
+#[derive(Debug)]
+pub enum StoreError {
+    /// the data of the component couldn't be parsed
+    Parse,
+    /// the component already has data of this kind, i.e. two files share a name
+    Duplicate,
+    /// the CSS of the component couldn't be parsed
+    Css(ParseError),
+}
+
 // #[derive(Clone, Copy)]
 pub struct ComponentStore {
     // this is public so we can call components.values() directly
@@ -73,35 +111,64 @@ impl ComponentStore {
         }
     }
 
-    #[inline]
-    pub fn find_component(&self, name: &str) -> Option<&Component> {
-        self.components.get(name)
-    }
+    /// Finds a component by the name it's used with, along with its full name. The name is
+    /// looked up in the namespace of the component using it first, then in each namespace around
+    /// that, so the components of a package can use each other without knowing the namespace
+    /// the website put the package in.
+    pub fn resolve_component(&self, namespace: &str, name: &str) -> Option<(&str, &Component)> {
+        let mut namespace = namespace;
+
+        loop {
+            let found = if namespace.is_empty() {
+                self.components.get_key_value(name)
+            } else {
+                self.components
+                    .get_key_value(&format!("{}.{}", namespace, name))
+            };
 
-    #[inline]
-    pub fn find_component_by_id(&self, id: i32) -> Option<&Component> {
-        for component in self.components.values() {
-            if component.id == id {
-                return Some(component);
+            if let Some((full_name, component)) = found {
+                return Some((full_name, component));
             }
+
+            if namespace.is_empty() {
+                return None;
+            }
+
+            namespace = namespace_of(namespace);
         }
+    }
 
-        None
+    #[inline]
+    pub fn find_component_by_id(&self, id: i32) -> Option<&Component> {
+        self.components
+            .values()
+            .find(|component| component.id == id)
     }
 
-    pub fn store_xml(&mut self, name: String, data: String) -> Result<(), ()> {
+    pub fn store_xml(
+        &mut self,
+        name: String,
+        data: String,
+        options: ComponentOptions,
+    ) -> Result<(), StoreError> {
+        let data = wrap_fragment(&data);
+
+        // ensures that parsing the component while building pages doesn't fail
+        if Document::parse(data.as_ref()).is_err() {
+            return Err(StoreError::Parse);
+        }
+
         let mut used_id = false;
         let id = self.next_id();
 
         {
             let entry = self.components.entry(name);
-            let mut component = entry.or_default();
+            let component = entry.or_default();
 
-            // ensures that component.document() doesn't fail
-            let document = match Document::parse(data.as_ref()) {
-                Ok(document) => document,
-                Err(_) => return Result::Err(()),
-            };
+            if !component.xml_data.is_empty() {
+                self.prev_id();
+                return Err(StoreError::Duplicate);
+            }
 
             if component.id == -1 {
                 component.id = id;
@@ -109,6 +176,7 @@ impl ComponentStore {
             }
 
             component.xml_data = data;
+            component.options = options;
         }
 
         if !used_id {
@@ -118,20 +186,29 @@ impl ComponentStore {
         Ok(())
     }
 
-    pub fn store_css(&mut self, name: String, data: String) -> Result<(), ()> {
+    pub fn store_css(&mut self, name: String, data: String, path: &Path) -> Result<(), StoreError> {
+        let stylesheet = css_imports::load_stylesheet(&data, path).map_err(StoreError::Css)?;
+
         let mut used_id = false;
         let id = self.next_id();
 
         {
             let entry = self.components.entry(name);
-            let mut component = entry.or_default();
+            let component = entry.or_default();
+
+            if component.has_css {
+                self.prev_id();
+                return Err(StoreError::Duplicate);
+            }
 
             if component.id == -1 {
                 component.id = id;
                 used_id = true;
             }
 
-            component.css_data = data;
+            component.stylesheet = stylesheet;
+            component.has_css = true;
+            component.css_path = Some(path.to_owned());
         }
 
         if !used_id {
@@ -141,6 +218,49 @@ impl ComponentStore {
         Ok(())
     }
 
+    /// Layers a theme underneath this store. Anything this store doesn't have - whole components,
+    /// or just the XML or CSS of one - is taken from the theme. Themes inherited first take
+    /// precedence over themes inherited later.
+    pub fn inherit(&mut self, theme: ComponentStore) {
+        for (name, theme_component) in theme.components {
+            if let Some(component) = self.components.get_mut(&name) {
+                if component.xml_data.is_empty() {
+                    component.xml_data = theme_component.xml_data;
+                    component.options = theme_component.options;
+                }
+
+                if !component.has_css && theme_component.has_css {
+                    component.stylesheet = theme_component.stylesheet;
+                    component.has_css = true;
+                    component.css_path = theme_component.css_path;
+                }
+            } else {
+                let id = self.next_id();
+                self.components.insert(
+                    name,
+                    Component {
+                        id,
+                        ..theme_component
+                    },
+                );
+            }
+        }
+    }
+
+    /// Scopes the CSS of every component to the elements of that component, so that components
+    /// can't style each other. Must be called once every component has been loaded.
+    pub fn scope_styles(&mut self) {
+        for (name, component) in self.components.iter_mut() {
+            if !component.has_css || component.scope.is_some() {
+                continue;
+            }
+
+            let scope = scope_attribute(name);
+            component.stylesheet.scope(&scope);
+            component.scope = Some(scope);
+        }
+    }
+
     #[inline]
     fn next_id(&mut self) -> i32 {
         self.id_counter.increment()
@@ -152,173 +272,609 @@ impl ComponentStore {
     }
 }
 
+/// Elements of components with CSS get an attribute starting with this, e.g. `data-gw-1a2b3c4d`.
+const SCOPE_PREFIX: &str = "data-gw-";
+
+/// The attribute a component's CSS is scoped to. It's based on the name of the component, so it
+/// stays the same between builds.
+fn scope_attribute(name: &str) -> String {
+    let hash = format!("{:x}", Sha256::digest(name.as_bytes()));
+
+    let mut attribute = String::with_capacity(SCOPE_PREFIX.len() + 8);
+    attribute.push_str(SCOPE_PREFIX);
+    attribute.push_str(&hash[..8]);
+    attribute
+}
+
+/// Comments starting with this are kept in the output, e.g. `<!--! licence -->`. The marker
+/// itself is removed, so conditional comments can be written as `<!--![if IE]>...<![endif]-->`.
+const COMMENT_MARKER: char = '!';
+
+/// Components can change how GoodWeb handles them with a `<?goodweb ...?>` processing
+/// instruction, e.g. `<?goodweb input="html"?>`.
+const OPTIONS_TARGET: &str = "goodweb";
+
+#[derive(Debug, Default)]
+pub struct ComponentOptions {
+    /// `input="html"`: the component is written in HTML rather than XML
+    pub html_input: bool,
+    /// `whitespace="collapse|preserve|trim"`: how the text of the component is handled
+    pub whitespace: Whitespace,
+    /// `props="label size"`: attributes which are only template variables, and so aren't
+    /// forwarded to the element marked with `GoodWeb-Spread`
+    pub props: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Whitespace {
+    /// Runs of whitespace become a single space, like a browser would show them. Whitespace at
+    /// the start or end of a block element is removed.
+    Collapse,
+    /// Text is written exactly as it is.
+    Preserve,
+    /// Whitespace at the start and end of every piece of text is removed.
+    Trim,
+}
+
+impl std::default::Default for Whitespace {
+    #[inline]
+    fn default() -> Whitespace {
+        Whitespace::Collapse
+    }
+}
+
+impl ComponentOptions {
+    /// Reads the options of the `<?goodweb ...?>` at the start of a component, before any of
+    /// its content.
+    pub fn from_source(data: &str) -> ComponentOptions {
+        let mut options = ComponentOptions::default();
+
+        let value = match find_options(data) {
+            Some(value) => value,
+            None => return options,
+        };
+
+        for (key, value) in parse_options(value) {
+            match key {
+                "input" => options.html_input = value == "html",
+                "whitespace" => {
+                    options.whitespace = match value {
+                        "collapse" => Whitespace::Collapse,
+                        "preserve" => Whitespace::Preserve,
+                        "trim" => Whitespace::Trim,
+                        _ => {
+                            println!(
+                                "[WARN] unknown whitespace '{}' - expected 'collapse', 'preserve' or 'trim'",
+                                value
+                            );
+                            Whitespace::Collapse
+                        }
+                    }
+                }
+                "props" => options.props = value.split_whitespace().map(str::to_owned).collect(),
+                _ => println!("[WARN] unknown option '{}' in <?{}?>", key, OPTIONS_TARGET),
+            }
+        }
+
+        options
+    }
+}
+
+/// Finds the text of the `<?goodweb ...?>` in front of the content of a component. Only
+/// whitespace, comments and other processing instructions may come before it, so an example in a
+/// comment or a `<pre>` isn't mistaken for the options.
+fn find_options(data: &str) -> Option<&str> {
+    let mut rest = data.trim_start_matches('\u{feff}');
+
+    loop {
+        rest = rest.trim_start();
+
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->")?;
+            rest = &rest[end + 3..];
+        } else if rest.starts_with("<?") {
+            let end = rest.find("?>")?;
+            let instruction = &rest[2..end];
+            rest = &rest[end + 2..];
+
+            let target_end = instruction
+                .find(|c: char| c.is_whitespace())
+                .unwrap_or(instruction.len());
+
+            if &instruction[..target_end] == OPTIONS_TARGET {
+                return Some(&instruction[target_end..]);
+            }
+        } else {
+            return None;
+        }
+    }
+}
+
+/// Parses `key="value"` pairs, separated by whitespace.
+fn parse_options(text: &str) -> Vec<(&str, &str)> {
+    let mut options = Vec::new();
+    let mut rest = text.trim_start();
+
+    while let Some(equals) = rest.find('=') {
+        let key = rest[..equals].trim();
+        let value = rest[equals + 1..].trim_start();
+
+        let quote = match value.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => quote,
+            _ => break,
+        };
+
+        let end = match value[1..].find(quote) {
+            Some(end) => end + 1,
+            None => break,
+        };
+
+        options.push((key, &value[1..end]));
+        rest = value[end + 1..].trim_start();
+    }
+
+    options
+}
+
+/// The element every component is wrapped in, so that components may have more than one
+/// top-level node (e.g. a `<dt>`/`<dd>` pair) and still be parsed as a single XML document.
+const FRAGMENT_ROOT: &str = "GoodWeb-Fragment";
+
+/// Wraps the data of a component in a fragment element. An XML declaration or doctype at the start
+/// has to stay outside of any element, so it's dropped - it'd never be output anyway.
+fn wrap_fragment(data: &str) -> String {
+    let mut data = data.trim_start_matches('\u{feff}').trim_start();
+
+    for prologue in &["<?xml ", "<!DOCTYPE"] {
+        if data.starts_with(prologue) {
+            let end = match *prologue {
+                "<!DOCTYPE" => doctype_length(data),
+                _ => data.find('>').map(|end| end + 1),
+            };
+
+            if let Some(end) = end {
+                data = data[end..].trim_start();
+            }
+        }
+    }
+
+    let mut wrapped = String::with_capacity(data.len() + FRAGMENT_ROOT.len() * 2 + 5);
+    wrapped.push('<');
+    wrapped.push_str(FRAGMENT_ROOT);
+    wrapped.push('>');
+    wrapped.push_str(data);
+    wrapped.push_str("</");
+    wrapped.push_str(FRAGMENT_ROOT);
+    wrapped.push('>');
+    wrapped
+}
+
+/// The length of a doctype. The `>`s inside of its internal subset, like in
+/// `<!DOCTYPE x [<!ENTITY y "<b>z</b>">]>`, don't end it.
+fn doctype_length(data: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut index = 0;
+
+    while let Some(character) = data[index..].chars().next() {
+        let rest = &data[index..];
+
+        let length = match character {
+            '"' | '\'' => rest[1..].find(character)? + 2,
+            '<' if rest.starts_with("<!--") => rest.find("-->")? + 3,
+            '[' => {
+                depth += 1;
+                1
+            }
+            ']' => {
+                depth -= 1;
+                1
+            }
+            '>' if depth == 0 => return Some(index + 1),
+            _ => character.len_utf8(),
+        };
+
+        index += length;
+    }
+
+    None
+}
+
 pub struct BuildResult {
-    xml: String,
+    document: Vec<HtmlNode>,
     components_used: Vec<i32>,
+    inline_styles: Option<usize>,
 }
 
 impl BuildResult {
     #[inline]
-    pub fn xml(&self) -> &String {
-        &self.xml
+    pub fn document(&self) -> &[HtmlNode] {
+        &self.document
+    }
+
+    #[inline]
+    pub fn document_mut(&mut self) -> &mut Vec<HtmlNode> {
+        &mut self.document
     }
 
     #[inline]
     pub fn components_used(&self) -> &Vec<i32> {
         &self.components_used
     }
+
+    /// If the page's CSS should be inlined, how large it may be at most before it's linked to
+    /// instead.
+    #[inline]
+    pub fn inline_styles(&self) -> Option<usize> {
+        self.inline_styles
+    }
+
+    /// Links to the given stylesheets wherever the page has a `<GoodWeb-Styles/>`.
+    pub fn link_styles(&mut self, hrefs: &[String]) {
+        let mut writer = HtmlWriter::new();
+
+        for href in hrefs {
+            writer.start_element("link");
+            writer.write_attribute("rel", "stylesheet");
+            writer.write_attribute("href", href);
+            writer.end_element();
+        }
+
+        fill_placeholder(
+            &mut self.document,
+            Placeholder::Styles,
+            &writer.end_document(),
+        );
+    }
+
+    /// Writes the given CSS in a `<style>` wherever the page has a `<GoodWeb-Styles/>`.
+    pub fn embed_styles(&mut self, css: &str) {
+        let mut writer = HtmlWriter::new();
+
+        if !css.is_empty() {
+            writer.start_element("style");
+            writer.write_text(css);
+            writer.end_element();
+        }
+
+        fill_placeholder(
+            &mut self.document,
+            Placeholder::Styles,
+            &writer.end_document(),
+        );
+    }
 }
 
-struct DocumentInformation {
+struct DocumentInformation<'c> {
     page_name: String,
+    config: &'c Config,
+    /// everything inside of a <GoodWeb-Head>, to be written at the <GoodWeb-HeadOutlet/>
+    head: Vec<HeadElement>,
+    has_head_outlet: bool,
+    /// set by <GoodWeb-Styles inline="true"/>, to the most CSS that may be inlined
+    inline_styles: Option<usize>,
 }
 
-// Used to box a 'Node' without owning any data
-struct OwnerlessNode<'a> {
-    node_data: &'a str,
+struct HeadElement {
+    /// elements with the same key replace one another, e.g. there's only ever one <title>
+    key: Option<String>,
+    node: HtmlNode,
 }
 
-struct HackyDocument<'a> {
-    input: &'a str,
-    blank_ref_1: Option<i32>,
-}
+impl DocumentInformation<'_> {
+    /// Adds the rendered contents of a <GoodWeb-Head> to the head of the page.
+    fn push_head(&mut self, nodes: Vec<HtmlNode>) {
+        for node in nodes {
+            let key = match &node {
+                HtmlNode::Element(element) => head_key(element),
+                _ => continue,
+            };
 
-impl<'a> OwnerlessNode<'a> {
-    pub fn from_node_unsafe<'b>(node: &Node) -> OwnerlessNode<'b> {
-        // TODO: use get_input
-        // i'm not an experienced user of rust :(
-        let input_exposed: HackyDocument = unsafe { std::mem::transmute_copy(node.document()) };
+            let element = HeadElement { key, node };
 
-        let document_text = input_exposed.input;
-        OwnerlessNode::from_node(node, document_text)
-    }
+            let existing = self.head.iter_mut().find(|existing| match &element.key {
+                Some(key) => existing.key.as_ref() == Some(key),
+                None => existing.node == element.node,
+            });
 
-    pub fn from_node<'b>(node: &Node, document: &'b str) -> OwnerlessNode<'b> {
-        OwnerlessNode {
-            node_data: &document[node.range()],
+            // the element that comes later, i.e. from deeper within the page, wins
+            match existing {
+                Some(existing) => *existing = element,
+                None => self.head.push(element),
+            }
         }
     }
 
-    pub fn from_root_node<'b>(document: &Document<'b>) -> OwnerlessNode<'b> {
-        // TODO: use get_input
-        // i'm not an experienced user of rust :(
-        let input_exposed: HackyDocument = unsafe { std::mem::transmute_copy(document) };
+    fn head_nodes(&self) -> Vec<HtmlNode> {
+        self.head
+            .iter()
+            .map(|element| element.node.clone())
+            .collect()
+    }
+}
+
+/// Computes the key that decides which head elements are duplicates of one another.
+fn head_key(element: &HtmlElement) -> Option<String> {
+    let name = element.name.as_str();
+
+    match name {
+        "title" | "base" => Some(name.to_owned()),
+        "meta" => {
+            if element.attribute("charset").is_some() {
+                return Some(String::from("meta charset"));
+            }
 
-        let document_text = input_exposed.input;
+            for attribute in &["name", "property", "http-equiv", "itemprop"] {
+                if let Some(value) = element.attribute(attribute) {
+                    return Some(format!("meta {}={}", attribute, value));
+                }
+            }
 
-        OwnerlessNode {
-            node_data: document_text,
+            None
         }
+        "link" => match element.attribute("rel") {
+            Some("canonical") => Some(String::from("link canonical")),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The parsed XML of every component in a store, so that every component is only parsed once
+/// no matter how many pages use it.
+pub struct ComponentDocuments<'a> {
+    documents: HashMap<i32, Document<'a>>,
+}
+
+impl<'a> ComponentDocuments<'a> {
+    pub fn parse(components: &'a ComponentStore) -> ComponentDocuments<'a> {
+        let documents = components
+            .components
+            .values()
+            // components with only CSS have nothing to parse
+            .filter(|component| !component.xml_data.is_empty())
+            .filter_map(|component| {
+                Some((component.id, Document::parse(&component.xml_data).ok()?))
+            })
+            .collect();
+
+        ComponentDocuments { documents }
+    }
+
+    #[inline]
+    fn find_document(&self, component: &Component) -> Option<&Document<'a>> {
+        self.documents.get(&component.id)
     }
 }
 
-fn get_input<'input>(document: &Document<'input>) -> &'input str {
-    let input_exposed: HackyDocument = unsafe { std::mem::transmute_copy(document) };
+/// Elements with this attribute get every attribute the component was used with that isn't one
+/// of its props, e.g. `<button GoodWeb-Spread="">`.
+const SPREAD_ATTRIBUTE: &str = "GoodWeb-Spread";
+
+// Everything about the use of a component that its nodes need to know
+struct ComponentContext<'a> {
+    options: &'a ComponentOptions,
+    // the namespace the component is in, which the components it uses are looked up in first
+    namespace: &'a str,
+    // the attribute to mark elements with, if the component has scoped CSS
+    scope: Option<&'a str>,
+    // the attributes to write on elements with GoodWeb-Spread, already computed
+    spread: Vec<(String, String)>,
+}
+
+// A node, along with the context of the component it's from
+struct SourceNode<'a> {
+    node: Node<'a, 'a>,
+    context: Rc<ComponentContext<'a>>,
+}
 
-    input_exposed.input
+impl<'a> SourceNode<'a> {
+    // the root of a component is its <GoodWeb-Fragment>, so its children are the component
+    #[inline]
+    pub fn from_document(
+        document: &'a Document<'a>,
+        context: ComponentContext<'a>,
+    ) -> SourceNode<'a> {
+        SourceNode {
+            node: document.root_element(),
+            context: Rc::new(context),
+        }
+    }
 }
 
 // TODO: use Result to detail errors
-pub fn build_page(
+pub fn build_page<'a>(
     name: String,
-    page: &Component,
-    components: &ComponentStore,
+    page: &'a Component,
+    components: &'a ComponentStore,
+    documents: &'a ComponentDocuments<'a>,
+    config: &Config,
 ) -> Option<BuildResult> {
-    let handlebars = handlebars::Handlebars::new();
-    let engine = TemplateEngine::new(&handlebars);
+    let handlebars = templating::markup_handlebars();
+    let plain_handlebars = templating::plain_handlebars();
+    let mut engine = TemplateEngine::new(&handlebars, &plain_handlebars);
+    engine.set("tokens", serde_json::Value::Object(config.tokens.clone()));
     let mut components_used: Vec<i32> = Vec::new();
 
-    // no idea how much we'll need, but let's allocate a pretty large buffer just in case
-    let mut writer = XmlWriter::with_capacity(
-        1_000,
-        Options {
-            use_single_quote: false,
-            indent: Indent::None,
-            attributes_indent: Indent::None,
-            ..Options::default()
-        },
-    );
+    // pages were validated when they were stored, so this can't fail
+    let page_document = Document::parse(&page.xml_data).ok()?;
+
+    let writer = HtmlWriter::new();
 
-    let mut goodweb_inner = &mut Vec::new();
+    let goodweb_inner = &mut Vec::new();
+    let mut page_info = DocumentInformation {
+        page_name: name,
+        config,
+        head: Vec::new(),
+        has_head_outlet: false,
+        inline_styles: None,
+    };
 
     // we pass in the state and let it own everything, and hope we get the String back
-    let mut writer = compute_recursive_pre(
-        &DocumentInformation { page_name: name },
+    let writer = compute_recursive_pre(
+        &mut page_info,
         writer,
         components,
-        OwnerlessNode::from_root_node(&page.document()),
+        documents,
+        SourceNode::from_document(
+            &page_document,
+            ComponentContext {
+                options: &page.options,
+                namespace: "",
+                scope: page.scope.as_deref(),
+                spread: Vec::new(),
+            },
+        ),
         &engine,
         &mut components_used,
         None,
         goodweb_inner,
-        true,
     )?;
 
     println!("left: {:?}", goodweb_inner.len());
 
-    let result = writer.end_document();
+    let mut document = writer.end_document();
+
+    if page_info.has_head_outlet {
+        fill_placeholder(
+            &mut document,
+            Placeholder::HeadOutlet,
+            &page_info.head_nodes(),
+        );
+    } else if !page_info.head.is_empty() {
+        println!(
+            "[WARN] page '{}' uses <GoodWeb-Head>, but has no <GoodWeb-HeadOutlet/> to put it in",
+            page_info.page_name
+        );
+    }
 
     Some(BuildResult {
-        xml: result,
-        components_used: components_used,
+        document,
+        components_used,
+        inline_styles: page_info.inline_styles,
     })
 }
 
 #[inline]
-fn compute_recursive_pre(
-    page_info: &DocumentInformation,
-    writer: XmlWriter,
-    components: &ComponentStore,
-    node: OwnerlessNode,
+#[allow(clippy::too_many_arguments)]
+fn compute_recursive_pre<'a>(
+    page_info: &mut DocumentInformation<'_>,
+    writer: HtmlWriter,
+    components: &'a ComponentStore,
+    documents: &'a ComponentDocuments<'a>,
+    node: SourceNode<'a>,
     engine: &TemplateEngine<'_, '_>,
     components_used: &mut Vec<i32>,
 
     component_attributes: Option<&[Attribute<'_>]>,
-    goodweb_inner: &mut Vec<OwnerlessNode>,
-
-    use_children: bool,
-) -> Option<XmlWriter> {
+    goodweb_inner: &mut Vec<SourceNode<'a>>,
+) -> Option<HtmlWriter> {
     let engine = match component_attributes {
         Some(attributes) => engine.compute_state(attributes)?,
         None => engine.compute_state(&[])?,
     };
 
-    let doc = Document::parse(node.node_data).unwrap();
-    let root = doc.root();
     compute_recursive(
         page_info,
         writer,
         components,
-        if use_children {
-            root.children()
-        } else {
-            root.first_child().unwrap().children()
-        },
+        documents,
+        node.node.children(),
+        &node.context,
         &engine,
         components_used,
         goodweb_inner,
     )
 }
 
-fn compute_recursive<'a, 'b>(
-    page_info: &DocumentInformation,
-    writer: XmlWriter,
-    components: &ComponentStore,
-    children: Children<'_, '_>,
+#[allow(clippy::too_many_arguments)]
+fn compute_recursive<'a>(
+    page_info: &mut DocumentInformation<'_>,
+    writer: HtmlWriter,
+    components: &'a ComponentStore,
+    documents: &'a ComponentDocuments<'a>,
+    children: Children<'a, 'a>,
+    context: &Rc<ComponentContext<'a>>,
     engine: &TemplateEngine<'_, '_>,
     components_used: &mut Vec<i32>,
 
-    goodweb_inner: &mut Vec<OwnerlessNode>,
-) -> Option<XmlWriter> {
+    goodweb_inner: &mut Vec<SourceNode<'a>>,
+) -> Option<HtmlWriter> {
     let mut writer = writer;
 
     for child in children {
         match child.node_type() {
             NodeType::Root => panic!("Should never be on a root node."),
-            NodeType::Comment => continue,
+            NodeType::Comment => {
+                let comment = child.text()?;
+                let comment = match page_info.config.comments.keep {
+                    KeepComments::None => continue,
+                    KeepComments::Marked => match comment.strip_prefix(COMMENT_MARKER) {
+                        Some(comment) => comment,
+                        None => continue,
+                    },
+                    KeepComments::All => comment.strip_prefix(COMMENT_MARKER).unwrap_or(comment),
+                };
+
+                if page_info.config.comments.templated {
+                    writer.write_comment(&engine.solve_plain(comment)?);
+                } else {
+                    writer.write_comment(comment);
+                }
+
+                continue;
+            }
             NodeType::PI => continue,
             NodeType::Text => {
-                writer.write_text(engine.solve(child.text()?)?.trim());
+                // the contents of <script> and <style> aren't HTML, so they're never escaped
+                let is_raw_text = match child.parent_element() {
+                    Some(parent) => is_raw_text_element(parent.tag_name().name()),
+                    None => false,
+                };
+
+                let segments = text_segments(&child)?;
+                let last = segments.len() - 1;
+
+                for (index, segment) in segments.into_iter().enumerate() {
+                    let text = match segment {
+                        // CDATA is written exactly as it is, without templating
+                        TextSegment::CData(text) => {
+                            if !text.is_empty() {
+                                writer.write_text(text);
+                            }
+
+                            continue;
+                        }
+                        TextSegment::Text(text) => text,
+                    };
+
+                    let text = if is_raw_text {
+                        engine.solve_plain(&text)?
+                    } else {
+                        engine.solve(&text)?
+                    };
+                    let text = if is_raw_text || writer.is_inside(PRESERVED_WHITESPACE_ELEMENTS) {
+                        text
+                    } else {
+                        apply_whitespace(
+                            text,
+                            &child,
+                            context.options.whitespace,
+                            index == 0,
+                            index == last,
+                        )
+                    };
+
+                    if text.is_empty() {
+                        continue;
+                    }
+
+                    if is_raw_text {
+                        writer.write_text(&text);
+                    } else {
+                        writer.write_html(&text);
+                    }
+                }
+
                 continue;
             }
             NodeType::Element => {
@@ -328,7 +884,7 @@ fn compute_recursive<'a, 'b>(
                     // TODO: support these
                     match get_goodweb_component(name) {
                         GoodWebComponent::Inner => {
-                            if goodweb_inner.len() == 0 {
+                            if goodweb_inner.is_empty() {
                                 panic!("[WARN] invalid GoodWeb-Inner declaration");
                             }
 
@@ -338,31 +894,65 @@ fn compute_recursive<'a, 'b>(
                                 page_info,
                                 writer,
                                 components,
+                                documents,
                                 top,
                                 engine,
                                 components_used,
                                 None,
                                 goodweb_inner,
-                                false,
                             )?;
 
                             continue;
                         }
                         GoodWebComponent::Styles => {
-                            let mut href = String::with_capacity(page_info.page_name.len() + 4);
-                            href.push_str(&page_info.page_name);
-                            href.push_str(".css");
+                            if let Some(limit) = inline_styles_limit(&child, &page_info.page_name) {
+                                page_info.inline_styles = Some(limit);
+                            }
 
-                            writer.start_element("link");
-                            writer.write_attribute("rel", "stylesheet");
-                            writer.write_attribute("href", &href); // TODO: know about page
-                            writer.end_element();
+                            // which stylesheets there are is only known once every page is built
+                            writer.write_placeholder(Placeholder::Styles);
+                            continue;
+                        }
+                        GoodWebComponent::Head => {
+                            let head_writer = compute_recursive_pre(
+                                page_info,
+                                HtmlWriter::new(),
+                                components,
+                                documents,
+                                SourceNode {
+                                    node: child,
+                                    context: context.clone(),
+                                },
+                                engine,
+                                components_used,
+                                None,
+                                goodweb_inner,
+                            )?;
 
+                            page_info.push_head(head_writer.end_document());
+                            continue;
+                        }
+                        GoodWebComponent::Raw => {
+                            // the contents are written exactly as they are, markup and all
+                            if let Some(source) = inner_source(&child) {
+                                writer.write_html(source);
+                            }
+
+                            continue;
+                        }
+                        GoodWebComponent::HeadOutlet => {
+                            if page_info.has_head_outlet {
+                                println!("[WARN] only the first <GoodWeb-HeadOutlet/> is used");
+                                continue;
+                            }
+
+                            page_info.has_head_outlet = true;
+                            writer.write_placeholder(Placeholder::HeadOutlet);
                             continue;
                         }
                         GoodWebComponent::None => {
                             println!(
-                                "Invalid GoodWeb component '{}' - Expected 'Inner' or 'Styles'.",
+                                "Invalid GoodWeb component '{}' - Expected 'Inner', 'Styles', 'Head', 'HeadOutlet' or 'Raw'.",
                                 name
                             );
                             continue;
@@ -370,29 +960,77 @@ fn compute_recursive<'a, 'b>(
                     }
                 }
 
-                if !is_first_char_uppercase(name)? {
+                if !is_component_name(name)? {
                     // if the first character is not uppercase, we treat it as some html element.
                     //
                     // html elements:
                     // - attributes are not computed into the template engine
                     // - body is analyzed
 
-                    writer.start_element(name);
+                    writer.start_element(&qualified_name(
+                        &child,
+                        child.tag_name().namespace(),
+                        name,
+                    ));
 
+                    // namespaces are only declared where they were in the source, e.g. on <svg>
+                    for namespace in declared_namespaces(&child) {
+                        let mut uri = String::with_capacity(namespace.uri().len());
+                        escape_html_into(&mut uri, namespace.uri());
+
+                        match namespace.name() {
+                            Some(prefix) => {
+                                writer.write_attribute(&format!("xmlns:{}", prefix), &uri)
+                            }
+                            None => writer.write_attribute("xmlns", &uri),
+                        }
+                    }
+
+                    let mut has_spread = false;
                     for attribute in child.attributes() {
-                        writer.write_attribute(attribute.name(), &engine.solve(attribute.value())?);
+                        if attribute.name() == SPREAD_ATTRIBUTE {
+                            has_spread = true;
+                            continue;
+                        }
+
+                        let name = qualified_name(&child, attribute.namespace(), attribute.name());
+                        let value = engine.solve(attribute.value())?;
+
+                        if let Some(value) = attribute_value(&name, attribute.value(), value) {
+                            writer.write_attribute(&name, &value);
+                        }
+                    }
+
+                    if has_spread {
+                        for (name, value) in context.spread.iter() {
+                            // classes and styles are added to, rather than replaced
+                            match name.as_str() {
+                                "class" => writer.append_attribute(name, value, " "),
+                                "style" => writer.append_attribute(name, value, ";"),
+                                _ => writer.write_attribute(name, value),
+                            }
+                        }
+                    }
+
+                    if let Some(scope) = context.scope {
+                        if !is_unstyled_element(name) {
+                            writer.write_attribute(scope, "");
+                        }
                     }
 
                     writer = compute_recursive_pre(
                         page_info,
                         writer,
                         components,
-                        OwnerlessNode::from_node_unsafe(&child),
+                        documents,
+                        SourceNode {
+                            node: child,
+                            context: context.clone(),
+                        },
                         engine,
                         components_used,
                         None,
                         goodweb_inner,
-                        false,
                     )?;
 
                     writer.end_element();
@@ -402,35 +1040,85 @@ fn compute_recursive<'a, 'b>(
                     // we don't need to write anything, we'll leave all the writing to
                     // the component's components.
 
-                    let component = match components.find_component(name) {
-                        Some(component) => component,
-                        None => {
-                            println!("[WARN] no component found for element '{}'", name);
-                            continue;
-                        }
-                    };
+                    let (full_name, component) =
+                        match components.resolve_component(context.namespace, name) {
+                            Some(component) => component,
+                            None => {
+                                println!("[WARN] no component found for element '{}'", name);
+                                continue;
+                            }
+                        };
 
                     if !components_used.contains(&component.id()) {
                         components_used.push(component.id());
                     }
 
-                    let raw_text: OwnerlessNode = OwnerlessNode::from_node_unsafe(&child);
+                    let document = match documents.find_document(component) {
+                        Some(document) => document,
+                        None => {
+                            println!("[WARN] component '{}' has no XML, only CSS", name);
+                            continue;
+                        }
+                    };
 
                     let len = goodweb_inner.len();
-                    goodweb_inner.push(raw_text);
+                    goodweb_inner.push(SourceNode {
+                        node: child,
+                        context: context.clone(),
+                    });
+
+                    // attributes that aren't props are passed on to the component's GoodWeb-Spread
+                    let mut spread = Vec::new();
+                    let mut has_spread = false;
+                    for attribute in child.attributes() {
+                        if attribute.name() == SPREAD_ATTRIBUTE {
+                            has_spread = true;
+                            continue;
+                        }
+
+                        if component
+                            .options
+                            .props
+                            .iter()
+                            .any(|prop| prop == attribute.name())
+                        {
+                            continue;
+                        }
+
+                        let name = qualified_name(&child, attribute.namespace(), attribute.name());
+                        let value = engine.solve(attribute.value())?;
+
+                        if let Some(value) = attribute_value(&name, attribute.value(), value) {
+                            spread.push((name, value));
+                        }
+                    }
+
+                    // a component marked with GoodWeb-Spread passes on what it was given too,
+                    // which is added to or replaces its own attributes like on an element
+                    if has_spread {
+                        spread.extend(context.spread.iter().cloned());
+                    }
 
                     writer = compute_recursive_pre(
                         page_info,
                         writer,
                         components,
-                        OwnerlessNode::from_root_node(&component.document()),
+                        documents,
+                        SourceNode::from_document(
+                            document,
+                            ComponentContext {
+                                options: &component.options,
+                                namespace: namespace_of(full_name),
+                                scope: component.scope.as_deref(),
+                                spread,
+                            },
+                        ),
                         engine,
                         components_used,
                         Some(child.attributes()),
                         // the <GoodWeb:Inner> will be determined by the children
                         // of the compnent
                         goodweb_inner,
-                        true,
                     )?;
 
                     // we want to get the goodweb_inner size the same as before so that
@@ -446,16 +1134,410 @@ fn compute_recursive<'a, 'b>(
     Some(writer)
 }
 
+/// Decides what an attribute is written with, if it's written at all. An attribute whose template
+/// computes to nothing is left out, as is a boolean attribute that computes to `false`.
+fn attribute_value(name: &str, template: &str, value: String) -> Option<String> {
+    // an attribute without a template is always written, `alt=""` is meant to be empty
+    if !template.contains("{{") {
+        return Some(value);
+    }
+
+    if is_boolean_attribute(name) {
+        return match value.trim() {
+            "" | "false" => None,
+            "true" => Some(name.to_owned()),
+            _ => Some(value),
+        };
+    }
+
+    if value.trim().is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// The name of an element or attribute, with the prefix its namespace has in the source.
+fn qualified_name(node: &Node<'_, '_>, namespace: Option<&str>, name: &str) -> String {
+    match namespace.and_then(|uri| node.lookup_prefix(uri)) {
+        Some(prefix) => format!("{}:{}", prefix, name),
+        None => name.to_owned(),
+    }
+}
+
+/// The namespaces declared on an element itself, rather than inherited from its parents.
+fn declared_namespaces<'a>(node: &Node<'a, '_>) -> impl Iterator<Item = &'a Namespace<'a>> {
+    let inherited = match node.parent_element() {
+        Some(parent) => parent.namespaces(),
+        None => &[],
+    };
+
+    node.namespaces()
+        .iter()
+        // the xml namespace is always declared
+        .filter(move |namespace| namespace.name() != Some("xml") && !inherited.contains(namespace))
+}
+
+#[inline]
+fn is_fragment_root(node: Option<Node<'_, '_>>) -> bool {
+    match node {
+        Some(node) => node.tag_name().name() == FRAGMENT_ROOT,
+        None => false,
+    }
+}
+
+/// Applies the whitespace option of a component to a piece of text. `at_start` and `at_end` say
+/// whether the piece is at the start or end of its text node, which may be split up by CDATA.
+fn apply_whitespace(
+    text: String,
+    node: &Node<'_, '_>,
+    whitespace: Whitespace,
+    at_start: bool,
+    at_end: bool,
+) -> String {
+    match whitespace {
+        // except for the whitespace around the top-level nodes of the file
+        Whitespace::Preserve if is_fragment_root(node.parent()) && text.trim().is_empty() => {
+            String::new()
+        }
+        Whitespace::Preserve => text,
+        Whitespace::Trim => {
+            let mut trimmed = text.as_str();
+
+            if at_start {
+                trimmed = trimmed.trim_start();
+            }
+
+            if at_end {
+                trimmed = trimmed.trim_end();
+            }
+
+            trimmed.to_owned()
+        }
+        Whitespace::Collapse => collapse_whitespace(
+            &text,
+            at_start && is_block_boundary(node, false),
+            at_end && is_block_boundary(node, true),
+        ),
+    }
+}
+
+enum TextSegment<'a> {
+    Text(Cow<'a, str>),
+    CData(&'a str),
+}
+
+/// Splits a text node up into its CDATA sections and the text around them. roxmltree merges
+/// them all into one node, so they're found in the source.
+fn text_segments<'a>(node: &Node<'a, 'a>) -> Option<Vec<TextSegment<'a>>> {
+    let input = node.document().input_text();
+
+    // text always runs up to the next node, or the end of its parent
+    let end = match node.next_sibling() {
+        Some(sibling) => sibling.range().start,
+        None => end_tag_start(&node.parent()?)?,
+    };
+    let source = &input[node.range().start..end];
+
+    if !source.contains("<![CDATA[") {
+        return Some(vec![TextSegment::Text(Cow::Borrowed(node.text()?))]);
+    }
+
+    let mut segments = Vec::new();
+    let mut rest = source;
+
+    while let Some(start) = rest.find("<![CDATA[") {
+        if start > 0 {
+            segments.push(TextSegment::Text(unescape_xml(&rest[..start])));
+        }
+
+        let cdata = &rest[start + "<![CDATA[".len()..];
+        let end = cdata.find("]]>")?;
+        segments.push(TextSegment::CData(&cdata[..end]));
+        rest = &cdata[end + "]]>".len()..];
+    }
+
+    if !rest.is_empty() {
+        segments.push(TextSegment::Text(unescape_xml(rest)));
+    }
+
+    Some(segments)
+}
+
+/// The source between the start and end tag of an element.
+fn inner_source<'a>(node: &Node<'a, 'a>) -> Option<&'a str> {
+    let start = node.first_child()?.range().start;
+    let end = end_tag_start(node)?;
+
+    Some(&node.document().input_text()[start..end])
+}
+
+#[inline]
+fn end_tag_start(node: &Node<'_, '_>) -> Option<usize> {
+    node.document().input_text()[..node.range().end].rfind("</")
+}
+
+/// The default for how much CSS `<GoodWeb-Styles inline="true"/>` inlines, about as much as fits
+/// in the first few packets of a response.
+const DEFAULT_INLINE_LIMIT: usize = 14_000;
+
+/// Reads `inline` and `inline-limit` off of a `<GoodWeb-Styles/>`, returning how many bytes of CSS
+/// may be inlined if it's inlined at all.
+fn inline_styles_limit(node: &Node<'_, '_>, page_name: &str) -> Option<usize> {
+    match node.attribute("inline") {
+        None | Some("false") => return None,
+        Some("true") => (),
+        Some(value) => {
+            println!(
+                "[WARN] page '{}': <GoodWeb-Styles inline=\"{}\"/> should be \"true\" or \"false\"",
+                page_name, value
+            );
+            return None;
+        }
+    }
+
+    let limit = match node.attribute("inline-limit") {
+        Some(limit) => limit,
+        None => return Some(DEFAULT_INLINE_LIMIT),
+    };
+
+    match limit.trim().parse() {
+        Ok(limit) => Some(limit),
+        Err(_) => {
+            println!(
+                "[WARN] page '{}': <GoodWeb-Styles inline-limit=\"{}\"/> isn't a number of bytes, using {}",
+                page_name, limit, DEFAULT_INLINE_LIMIT
+            );
+            Some(DEFAULT_INLINE_LIMIT)
+        }
+    }
+}
+
+/// Resolves the character references in a piece of XML text. The text has already been parsed,
+/// so there are only the predefined entities and numeric references.
+pub fn unescape_xml(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+
+        let reference = &rest[1..end];
+        let character = match reference {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if reference.starts_with("#x") => u32::from_str_radix(&reference[2..], 16)
+                .ok()
+                .and_then(std::char::from_u32),
+            _ if reference.starts_with('#') => {
+                reference[1..].parse().ok().and_then(std::char::from_u32)
+            }
+            _ => None,
+        };
+
+        match character {
+            Some(character) => {
+                unescaped.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    unescaped.push_str(rest);
+    Cow::Owned(unescaped)
+}
+
+/// Elements whose text is always written exactly as it is.
+const PRESERVED_WHITESPACE_ELEMENTS: &[&str] = &["pre", "textarea", "code", "script", "style"];
+
+/// Turns every run of whitespace into a single space, and removes it entirely at the start or end
+/// if the text is up against the edge of a block.
+fn collapse_whitespace(text: &str, trim_start: bool, trim_end: bool) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut in_whitespace = false;
+
+    for character in text.chars() {
+        if character.is_ascii_whitespace() {
+            in_whitespace = true;
+            continue;
+        }
+
+        if in_whitespace && !(trim_start && collapsed.is_empty()) {
+            collapsed.push(' ');
+        }
+
+        in_whitespace = false;
+        collapsed.push(character);
+    }
+
+    if in_whitespace && !(trim_end || trim_start && collapsed.is_empty()) {
+        collapsed.push(' ');
+    }
+
+    collapsed
+}
+
+/// Whether whitespace before (or `after`) a node is insignificant, i.e. the sibling there is an
+/// element that isn't inline, or there's no sibling and the node is at the start or end of a
+/// block.
+fn is_block_boundary(node: &Node<'_, '_>, after: bool) -> bool {
+    let (element, sibling) = if after {
+        (node.next_sibling_element(), node.next_sibling())
+    } else {
+        (node.prev_sibling_element(), node.prev_sibling())
+    };
+
+    let sibling = match sibling {
+        Some(sibling) => sibling,
+        None => return is_block_edge(node, after),
+    };
+
+    // comments and processing instructions aren't written, so look past them
+    if !sibling.is_element() && !sibling.is_text() {
+        return match element {
+            Some(element) => is_block_element(element.tag_name().name()),
+            None => is_block_edge(node, after),
+        };
+    }
+
+    // whitespace between the node and a block goes away too
+    if sibling.is_text() {
+        return match sibling.text() {
+            Some(text) if text.trim().is_empty() => is_block_boundary(&sibling, after),
+            _ => false,
+        };
+    }
+
+    is_block_element(sibling.tag_name().name())
+}
+
+/// Whether a node without a sibling before (or `after`) it is at the edge of a block. Inline
+/// parents like `<b>` don't count, so `Hello<b> world</b>` keeps its space - it's their own
+/// siblings that decide.
+fn is_block_edge(node: &Node<'_, '_>, after: bool) -> bool {
+    let parent = match node.parent_element() {
+        Some(parent) => parent,
+        None => return true,
+    };
+    let name = parent.tag_name().name();
+
+    // the roots of fragments and the elements of components are treated as blocks
+    if name == FRAGMENT_ROOT || is_component_name(name).unwrap_or(false) || is_block_element(name) {
+        return true;
+    }
+
+    is_block_boundary(&parent, after)
+}
+
+#[inline]
+fn is_block_element(name: &str) -> bool {
+    matches!(
+        name,
+        "address"
+            | "article"
+            | "aside"
+            | "blockquote"
+            | "body"
+            | "br"
+            | "dd"
+            | "details"
+            | "div"
+            | "dl"
+            | "dt"
+            | "fieldset"
+            | "figcaption"
+            | "figure"
+            | "footer"
+            | "form"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "head"
+            | "header"
+            | "hr"
+            | "html"
+            | "li"
+            | "link"
+            | "main"
+            | "meta"
+            | "nav"
+            | "ol"
+            | "p"
+            | "pre"
+            | "script"
+            | "section"
+            | "style"
+            | "summary"
+            | "table"
+            | "tbody"
+            | "td"
+            | "tfoot"
+            | "th"
+            | "thead"
+            | "title"
+            | "tr"
+            | "ul"
+            | "GoodWeb-Head"
+            | "GoodWeb-HeadOutlet"
+            | "GoodWeb-Styles"
+    )
+}
+
+/// Elements which are never shown, so there's no point in marking them with a scope.
+#[inline]
+fn is_unstyled_element(name: &str) -> bool {
+    matches!(
+        name,
+        "base" | "head" | "link" | "meta" | "script" | "style" | "title"
+    )
+}
+
 #[inline]
 fn is_first_char_uppercase(slice: &str) -> Option<bool> {
     let character = slice.chars().next()?;
-    Some(character >= 'A' && character <= 'Z')
+    Some(character.is_ascii_uppercase())
+}
+
+/// The namespace a component is in, e.g. `blog` for `blog.Card`.
+#[inline]
+fn namespace_of(name: &str) -> &str {
+    name.rsplit_once('.').map_or("", |(namespace, _)| namespace)
+}
+
+/// Components may be namespaced by the directories they live in, e.g. `<blog.Card>`.
+/// Only the last segment of the name decides whether or not it is a component.
+#[inline]
+fn is_component_name(name: &str) -> Option<bool> {
+    is_first_char_uppercase(name.rsplit('.').next()?)
 }
 
 enum GoodWebComponent {
     None,
     Inner,
     Styles,
+    Head,
+    HeadOutlet,
+    Raw,
 }
 
 #[inline]
@@ -468,6 +1550,125 @@ fn get_goodweb_component(name: &str) -> GoodWebComponent {
     match name {
         "GoodWeb-Inner" => GoodWebComponent::Inner,
         "GoodWeb-Styles" => GoodWebComponent::Styles,
+        "GoodWeb-Head" => GoodWebComponent::Head,
+        "GoodWeb-HeadOutlet" => GoodWebComponent::HeadOutlet,
+        "GoodWeb-Raw" => GoodWebComponent::Raw,
         _ => GoodWebComponent::None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_writer::OutputMode;
+
+    fn store(components: &[(&str, &str)]) -> ComponentStore {
+        let mut store = ComponentStore::new();
+        for (name, xml) in components {
+            store
+                .store_xml(
+                    name.to_string(),
+                    xml.to_string(),
+                    ComponentOptions::from_source(xml),
+                )
+                .unwrap();
+        }
+        store
+    }
+
+    fn build(page: &str, components: &[(&str, &str)]) -> String {
+        let components = store(components);
+        let documents = ComponentDocuments::parse(&components);
+        let pages = store(&[("index", page)]);
+        let config = Config::default();
+
+        let result = build_page(
+            "index".to_owned(),
+            &pages.components["index"],
+            &components,
+            &documents,
+            &config,
+        )
+        .unwrap();
+
+        crate::html_writer::serialize(result.document(), OutputMode::Xml)
+    }
+
+    #[test]
+    fn components_are_looked_up_in_their_own_namespace_first() {
+        let store = store(&[
+            ("ui.Card", "<div/>"),
+            ("ui.Button", "<button/>"),
+            ("Button", "<a/>"),
+        ]);
+        let resolve = |namespace, name| store.resolve_component(namespace, name).map(|(n, _)| n);
+
+        assert_eq!(resolve("ui", "Button"), Some("ui.Button"));
+        assert_eq!(resolve("", "Button"), Some("Button"));
+        assert_eq!(resolve("ui.forms", "Card"), Some("ui.Card"));
+        assert_eq!(resolve("", "Card"), None);
+        assert_eq!(resolve("", "ui.Card"), Some("ui.Card"));
+    }
+
+    #[test]
+    fn options_are_only_read_before_the_content() {
+        let options = |source| ComponentOptions::from_source(source).html_input;
+
+        assert!(options("<?goodweb input=\"html\"?><p>Hi</p>"));
+        assert!(options(
+            "<?xml version=\"1.0\"?>\n<!-- a card -->\n<?goodweb input=\"html\"?>\n<div/>"
+        ));
+        assert!(!options("<!-- <?goodweb input=\"html\"?> --><p>Hi</p>"));
+        assert!(!options(
+            "<pre><![CDATA[<?goodweb input=\"html\"?>]]></pre>"
+        ));
+        assert!(!options("<p>Hi</p><?goodweb input=\"html\"?>"));
+        assert!(!options("<?goodwebby input=\"html\"?><p>Hi</p>"));
+    }
+
+    #[test]
+    fn inline_elements_keep_the_space_at_their_edges() {
+        assert_eq!(
+            build("<p>Hello<b> world</b></p>", &[]),
+            "<p>Hello<b> world</b></p>"
+        );
+        assert_eq!(
+            build("<p><a>Read <em> more</em></a></p>", &[]),
+            "<p><a>Read <em> more</em></a></p>"
+        );
+        assert_eq!(
+            build("<p><b>Hello </b>world </p>", &[]),
+            "<p><b>Hello </b>world</p>"
+        );
+        assert_eq!(
+            build("<div> <b> Hi </b> </div>", &[]),
+            "<div><b>Hi</b></div>"
+        );
+        assert_eq!(
+            build("<p><Label/></p>", &[("Label", "<b> Hi </b>")]),
+            "<p><b>Hi</b></p>"
+        );
+    }
+
+    #[test]
+    fn components_can_spread_onto_components() {
+        let components = [
+            (
+                "Button",
+                "<?goodweb props=\"label\"?><button class=\"btn\" GoodWeb-Spread=\"\">{{ label }}</button>",
+            ),
+            (
+                "Card",
+                "<?goodweb props=\"title\"?><Button label=\"{{ title }}\" class=\"card\" type=\"submit\" GoodWeb-Spread=\"\"/>",
+            ),
+        ];
+
+        assert_eq!(
+            build(
+                "<Card title=\"Go\" class=\"wide\" type=\"button\" id=\"go\"/>",
+                &components
+            ),
+            "<button class=\"btn card wide\" type=\"button\" id=\"go\">Go</button>"
+        );
+    }
+}
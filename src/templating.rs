@@ -6,32 +6,62 @@ use roxmltree::Attribute;
 use serde_json::{Map, Number, Value};
 
 /// Represents an engine used for computing
+///
+/// Templates are computed either as markup, where everything is escaped exactly once for HTML
+/// (except for the contents of a `{{{ triple-stash }}}`), or as plain text, which is never escaped.
 pub struct TemplateEngine<'a, 'b> {
     handlebars_instance: &'a Handlebars<'b>,
+    plain_instance: &'a Handlebars<'b>,
     data: Map<String, Value>,
 }
 
+/// Creates the Handlebars instance used by `TemplateEngine::solve`, which escapes `{{ values }}`.
+pub fn markup_handlebars<'b>() -> Handlebars<'b> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(escape_html);
+    handlebars
+}
+
+/// Creates the Handlebars instance used by `TemplateEngine::solve_plain`, which escapes nothing.
+pub fn plain_handlebars<'b>() -> Handlebars<'b> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars
+}
+
 impl<'a, 'b> TemplateEngine<'a, 'b> {
     #[inline]
-    pub fn new(engine: &'a Handlebars<'b>) -> TemplateEngine<'a, 'b> {
+    pub fn new(
+        engine: &'a Handlebars<'b>,
+        plain_engine: &'a Handlebars<'b>,
+    ) -> TemplateEngine<'a, 'b> {
         TemplateEngine {
             handlebars_instance: engine,
+            plain_instance: plain_engine,
             data: Map::new(),
         }
     }
 
-    pub fn compute_state(&self, attributes: &'a [Attribute<'b>]) -> Option<TemplateEngine<'a, 'b>> {
+    /// Sets a variable every template can use, e.g. the `tokens` from the config.
+    #[inline]
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.data.insert(name.to_owned(), value);
+    }
+
+    pub fn compute_state(&self, attributes: &[Attribute<'_>]) -> Option<TemplateEngine<'a, 'b>> {
         let mut new_state = self.data.clone();
 
-        // compute new state using all of the attributes of the old
+        // compute new state using all of the attributes of the old. the state holds plain values,
+        // they're escaped once they're used in markup.
         for attribute in attributes {
-            let result = self.solve(attribute.value())?;
+            let result = self.solve_plain(attribute.value())?;
             new_state.insert(attribute.name().to_owned(), str_to_json(result));
         }
 
         // new state computed
         Some(TemplateEngine {
             handlebars_instance: self.handlebars_instance,
+            plain_instance: self.plain_instance,
             data: new_state,
         })
     }
@@ -41,20 +71,84 @@ impl<'a, 'b> TemplateEngine<'a, 'b> {
         Value::Object(self.data.clone())
     }
 
+    /// Computes a template into HTML. Both the text of the template and `{{ values }}` are
+    /// escaped, `{{{ values }}}` are trusted and left as-is.
     #[inline]
     pub fn solve(&self, needs_computation: &str) -> Option<String> {
-        match self
-            .handlebars_instance
+        self.handlebars_instance
+            .render_template(&escape_literals(needs_computation), &self.data())
+            .ok()
+    }
+
+    /// Computes a template into plain text, without escaping anything.
+    #[inline]
+    pub fn solve_plain(&self, needs_computation: &str) -> Option<String> {
+        self.plain_instance
             .render_template(needs_computation, &self.data())
-        {
-            Ok(result) => Some(result),
-            Err(_) => None,
+            .ok()
+    }
+}
+
+/// Escapes the text of a template for HTML, leaving all of the `{{ expressions }}` alone.
+fn escape_literals(template: &str) -> String {
+    let mut escaped = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        escape_html_into(&mut escaped, &rest[..start]);
+        rest = &rest[start..];
+
+        let terminator = if rest.starts_with("{{{") {
+            "}}}"
+        } else if rest.starts_with("{{!--") {
+            "--}}"
+        } else {
+            "}}"
+        };
+
+        let end = match rest[2..].find(terminator) {
+            Some(end) => end + 2 + terminator.len(),
+            // let handlebars report the unclosed expression
+            None => rest.len(),
+        };
+
+        escaped.push_str(&rest[..end]);
+        rest = &rest[end..];
+    }
+
+    escape_html_into(&mut escaped, rest);
+    escaped
+}
+
+fn escape_html(data: &str) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    escape_html_into(&mut escaped, data);
+    escaped
+}
+
+/// Escapes text so that it can be used both in HTML text and in a double-quoted attribute.
+pub fn escape_html_into(output: &mut String, data: &str) {
+    for character in data.chars() {
+        match character {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            _ => output.push(character),
         }
     }
 }
 
 #[inline]
 fn str_to_json(string: String) -> Value {
+    // so that `{{#if disabled}}` is false for `disabled="false"`
+    match string.as_str() {
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        _ => (),
+    }
+
     match string.parse::<f64>() {
         Ok(parsed) => f64_to_json(parsed, string),
         Err(_) => Value::String(string),
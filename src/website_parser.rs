@@ -1,11 +1,33 @@
-use crate::page_builder::{Component, ComponentStore};
+use crate::html_parser;
+use crate::page_builder::{ComponentOptions, ComponentStore, StoreError};
 use std::ffi::OsStr;
 use std::path::Path;
 use walkdir::WalkDir;
 
+/// Reads every component in a directory. Components in subdirectories are namespaced by
+/// the directories they're in, so `blog/Card.xml` is known as `blog.Card`.
+///
+/// Two files which end up with the same name, and HTML which can't be turned into XML, are reported
+/// as errors, and no store is returned.
 pub fn compute_components(components_directory: &Path) -> Option<ComponentStore> {
     let mut component_store: ComponentStore = ComponentStore::new();
-    let walker = WalkDir::new(components_directory);
+    compute_components_into(&mut component_store, components_directory, None)?;
+    Some(component_store)
+}
+
+/// Reads every component in a directory into an existing store. If a namespace is given, every
+/// component is placed inside of it, e.g. `Button.xml` in the `bootstrap` namespace is known as
+/// `bootstrap.Button`.
+pub fn compute_components_into(
+    component_store: &mut ComponentStore,
+    components_directory: &Path,
+    namespace: Option<&str>,
+) -> Option<()> {
+    let mut has_errors = false;
+
+    // sorted so that the order files are read in (and thus errors) is the same every build
+    let walker =
+        WalkDir::new(components_directory).sort_by(|a, b| a.file_name().cmp(b.file_name()));
 
     for file in walker.into_iter() {
         let file: walkdir::DirEntry = match file {
@@ -32,7 +54,17 @@ pub fn compute_components(components_directory: &Path) -> Option<ComponentStore>
             Some(extension) => get_component_extension(extension),
         };
 
-        let name = file_path.file_stem()?.to_str()?.to_owned();
+        let name = match component_name(components_directory, file_path, namespace) {
+            Some(name) => name,
+            None => {
+                println!(
+                    "[WARN] couldn't compute a component name for '{}'",
+                    file_path.display()
+                );
+                continue;
+            }
+        };
+
         let data = match std::fs::read_to_string(file_path) {
             Ok(data) => data,
             Err(_) => {
@@ -42,41 +74,122 @@ pub fn compute_components(components_directory: &Path) -> Option<ComponentStore>
         };
 
         match extension {
-            ComponentExtension::Xml => match component_store.store_xml(name, data) {
-                Ok(_) => continue,
-                Err(_) => {
-                    println!(
-                        "[WARN] couldn't parse XML of component '{}'",
-                        file_path.display()
-                    );
-                    continue;
+            ComponentExtension::Xml | ComponentExtension::Html => {
+                let options = ComponentOptions::from_source(&data);
+
+                // components written in HTML are turned into XML, then handled like any other
+                let data = match extension {
+                    ComponentExtension::Html => html_parser::to_xml(&data),
+                    _ if options.html_input => html_parser::to_xml(&data),
+                    _ => Ok(data),
+                };
+
+                let data = match data {
+                    Ok(data) => data,
+                    Err(error) => {
+                        println!(
+                            "[ERR] couldn't read HTML of component '{}' - {}",
+                            file_path.display(),
+                            error
+                        );
+                        has_errors = true;
+                        continue;
+                    }
+                };
+
+                match component_store.store_xml(name.clone(), data, options) {
+                    Ok(_) => continue,
+                    Err(StoreError::Duplicate) => {
+                        report_duplicate(&name, file_path);
+                        has_errors = true;
+                        continue;
+                    }
+                    Err(StoreError::Parse) | Err(StoreError::Css(_)) => {
+                        println!(
+                            "[WARN] couldn't parse XML of component '{}'",
+                            file_path.display()
+                        );
+                        continue;
+                    }
                 }
-            },
-            ComponentExtension::Css => match component_store.store_css(name, data) {
-                Ok(_) => continue,
-                Err(_) => {
-                    println!(
-                        "[WARN] couldn't parse CSS of component '{}'",
-                        file_path.display()
-                    );
-                    continue;
+            }
+            // partials like `_buttons.css` are only there to be imported
+            ComponentExtension::Css if is_partial(file_path) => continue,
+            ComponentExtension::Css => {
+                match component_store.store_css(name.clone(), data, file_path) {
+                    Ok(_) => continue,
+                    Err(StoreError::Duplicate) => {
+                        report_duplicate(&name, file_path);
+                        has_errors = true;
+                        continue;
+                    }
+                    // the error says which file it's in, which may be a partial
+                    Err(StoreError::Css(error)) => {
+                        println!("[WARN] couldn't parse CSS - {}", error);
+                        continue;
+                    }
+                    Err(StoreError::Parse) => continue,
                 }
-            },
-            ComponentExtension::Invalid => {
-                println!(
-                    "[WARN] found invalid extension type for component: '{}'",
-                    file.path().display()
-                );
-                continue;
             }
+            // anything else, like images and fonts, may be used by the CSS
+            ComponentExtension::Invalid => continue,
         }
     }
 
-    Some(component_store)
+    if has_errors {
+        return None;
+    }
+
+    Some(())
+}
+
+/// Computes the name of a component from its path, relative to the directory of components.
+/// `blog/posts/Card.xml` turns into `blog.posts.Card`.
+fn component_name(
+    components_directory: &Path,
+    file_path: &Path,
+    namespace: Option<&str>,
+) -> Option<String> {
+    let relative = file_path.strip_prefix(components_directory).ok()?;
+    let mut name = String::new();
+
+    if let Some(namespace) = namespace {
+        name.push_str(namespace);
+        name.push('.');
+    }
+
+    if let Some(parent) = relative.parent() {
+        for directory in parent.iter() {
+            name.push_str(directory.to_str()?);
+            name.push('.');
+        }
+    }
+
+    name.push_str(file_path.file_stem()?.to_str()?);
+    Some(name)
+}
+
+#[inline]
+fn is_partial(file_path: &Path) -> bool {
+    file_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('_'))
+}
+
+#[inline]
+fn report_duplicate(name: &str, file_path: &Path) {
+    println!(
+        "[ERR] component '{}' is defined more than once - '{}' conflicts with an earlier file",
+        name,
+        file_path.display()
+    );
 }
 
+#[derive(Clone, Copy)]
 enum ComponentExtension {
     Xml,
+    Html,
     Css,
     Invalid,
 }
@@ -89,7 +202,7 @@ fn get_component_extension(extension: &OsStr) -> ComponentExtension {
     };
 
     match extension {
-        "html" => ComponentExtension::Xml,
+        "html" => ComponentExtension::Html,
         "xml" => ComponentExtension::Xml,
         "css" => ComponentExtension::Css,
         _ => ComponentExtension::Invalid,